@@ -1,12 +1,15 @@
 use std::collections::HashMap;
-use std::default::Default;
+use std::fmt;
 
-use vm::Node;
+use optimizer::{optimize_code, stride, OptimizationOptions};
+use parser::Location;
+use vm::{InstructionKind, Node, Tracer, NUMBER_OF_CELLS};
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct AnalysisResults {
     total: u32,
-    nodes: HashMap<Node, u32>,
+    nodes: HashMap<InstructionKind, u32>,
 }
 
 impl AnalysisResults {
@@ -14,52 +17,198 @@ impl AnalysisResults {
         self.total += nested.total;
 
         for (k, v) in &nested.nodes {
-            self.nodes
-                .entry(k.clone())
-                .and_modify(|n| *n += v)
-                .or_insert(*v);
+            self.nodes.entry(*k).and_modify(|n| *n += v).or_insert(*v);
         }
     }
+
+    /// The number of nodes, including nested nodes
+    pub fn total(&self) -> u32 {
+        self.total
+    }
+
+    /// The number of nodes of a kind, including nested nodes
+    pub fn count(&self, kind: InstructionKind) -> u32 {
+        self.nodes.get(&kind).cloned().unwrap_or(0)
+    }
+
+    /// The number of nodes of every kind, in the order of `InstructionKind::ALL`
+    pub fn counts(&self) -> Vec<(InstructionKind, u32)> {
+        InstructionKind::ALL
+            .iter()
+            .map(|kind| (*kind, self.count(*kind)))
+            .collect()
+    }
+
+    /// Serializes the results to a JSON object that always contains every instruction kind
+    pub fn to_json(&self) -> String {
+        let nodes: Vec<String> = self
+            .counts()
+            .iter()
+            .map(|(kind, count)| format!("\"{}\":{}", kind.name(), count))
+            .collect();
+
+        format!(
+            "{{\"total\":{},\"nodes\":{{{}}}}}",
+            self.total,
+            nodes.join(",")
+        )
+    }
 }
 
-impl Default for AnalysisResults {
-    fn default() -> Self {
-        AnalysisResults {
-            total: 0,
-            nodes: HashMap::new(),
+/// A table of the node counts, leaving out kinds that do not occur
+impl fmt::Display for AnalysisResults {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        writeln!(f, "{:<12}{:>8}{:>8}", "Instruction", "Count", "Share")?;
+
+        for (kind, count) in self.counts() {
+            if count > 0 {
+                writeln!(
+                    f,
+                    "{:<12}{:>8}{:>7.1}%",
+                    kind.name(),
+                    count,
+                    f64::from(count) * 100.0 / f64::from(self.total)
+                )?;
+            }
         }
+
+        write!(f, "{:<12}{:>8}", "total", self.total)
+    }
+}
+
+/// Counts the nodes the VM executes by kind, when passed as the tracer of a run
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Profile {
+    executed: [u64; InstructionKind::ALL.len()],
+}
+
+impl Profile {
+    pub fn count(&self, kind: InstructionKind) -> u64 {
+        self.executed[kind as usize]
+    }
+
+    pub fn total(&self) -> u64 {
+        self.executed.iter().sum()
+    }
+}
+
+impl Tracer for Profile {
+    fn trace(&mut self, node: &Node) {
+        self.executed[node.kind() as usize] += 1;
+    }
+}
+
+/// The node counts of some code before and after optimizing it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    pub unoptimized: AnalysisResults,
+    pub optimized: AnalysisResults,
+    /// How many nodes of the optimized code ran, if it was profiled
+    pub profile: Option<Profile>,
+}
+
+impl Comparison {
+    pub fn new(unoptimized: &[Node], optimized: &[Node]) -> Comparison {
+        let analyzer = SimpleAnalyzer {};
+
+        Comparison {
+            unoptimized: analyzer.analyze(unoptimized),
+            optimized: analyzer.analyze(optimized),
+            profile: None,
+        }
+    }
+
+    /// How many percent fewer nodes of a kind there are after optimizing, if there were any before
+    pub fn reduction(&self, kind: InstructionKind) -> Option<f64> {
+        reduction(self.unoptimized.count(kind), self.optimized.count(kind))
+    }
+
+    pub fn total_reduction(&self) -> Option<f64> {
+        reduction(self.unoptimized.total(), self.optimized.total())
+    }
+}
+
+fn reduction(before: u32, after: u32) -> Option<f64> {
+    if before == 0 {
+        None
+    } else {
+        Some((f64::from(before) - f64::from(after)) * 100.0 / f64::from(before))
+    }
+}
+
+/// A table of the node counts side by side, leaving out kinds that occur in neither version
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let row = |f: &mut fmt::Formatter,
+                   name: &str,
+                   before: u32,
+                   after: u32,
+                   executed: Option<u64>|
+         -> Result<(), fmt::Error> {
+            write!(f, "{:<12}{:>12}{:>10}", name, before, after)?;
+            match reduction(before, after) {
+                Some(percent) => write!(f, "{:>10.1}%", percent)?,
+                None => write!(f, "{:>11}", "-")?,
+            }
+            match executed {
+                Some(count) => writeln!(f, "{:>14}", count),
+                None => writeln!(f),
+            }
+        };
+
+        write!(
+            f,
+            "{:<12}{:>12}{:>10}{:>11}",
+            "Instruction", "Unoptimized", "Optimized", "Reduction"
+        )?;
+        if self.profile.is_some() {
+            write!(f, "{:>14}", "Executed")?;
+        }
+        writeln!(f)?;
+
+        for kind in InstructionKind::ALL.iter() {
+            let before = self.unoptimized.count(*kind);
+            let after = self.optimized.count(*kind);
+
+            if before > 0 || after > 0 {
+                let executed = self.profile.as_ref().map(|p| p.count(*kind));
+                row(f, kind.name(), before, after, executed)?;
+            }
+        }
+
+        let executed = self.profile.as_ref().map(Profile::total);
+        row(
+            f,
+            "total",
+            self.unoptimized.total(),
+            self.optimized.total(),
+            executed,
+        )
     }
 }
 
 pub trait Analyzer {
-    fn analyze(&self, code: &[Node]) -> AnalysisResults;
+    type Results;
+
+    fn analyze(&self, code: &[Node]) -> Self::Results;
 }
 
 pub struct SimpleAnalyzer {}
 
 impl Analyzer for SimpleAnalyzer {
+    type Results = AnalysisResults;
+
     fn analyze(&self, code: &[Node]) -> AnalysisResults {
         code.iter()
             .fold(AnalysisResults::default(), move |mut memo, v| {
                 memo.total += 1;
 
-                {
-                    let entry = match v {
-                        Node::Shift(_) => memo.nodes.entry(Node::Shift(0)),
-                        Node::Inc(_, _, _) => memo.nodes.entry(Node::Inc(0, 0, false)),
-                        Node::Dec(_, _, _) => memo.nodes.entry(Node::Dec(0, 0, false)),
-                        Node::Mul(_, _, _, _) => memo.nodes.entry(Node::Mul(0, 0, 0, false)),
-                        Node::Assign(_, _, _) => memo.nodes.entry(Node::Assign(0, 0, false)),
-                        Node::Scan(_) => memo.nodes.entry(Node::Scan(0)),
-                        Node::Out(_, _) => memo.nodes.entry(Node::Out(0, false)),
-                        Node::In(_, _) => memo.nodes.entry(Node::Out(0, false)),
-                        Node::Comment(_) => memo.nodes.entry(Node::Comment(' ')),
-                        Node::Conditional(_) => memo.nodes.entry(Node::Conditional(vec![])),
-                    };
-                    entry.and_modify(|e| *e += 1).or_insert(1);
-                }
-                if let Node::Conditional(v) = v {
-                    let nested = self.analyze(v);
+                memo.nodes
+                    .entry(v.kind())
+                    .and_modify(|e| *e += 1)
+                    .or_insert(1);
+                if let Some((_, body)) = v.as_loop() {
+                    let nested = self.analyze(body);
                     memo.merge(&nested);
                 }
 
@@ -68,9 +217,514 @@ impl Analyzer for SimpleAnalyzer {
     }
 }
 
+/// Finds the loops whose condition cell provably never changes inside the body. Once entered, such a
+/// loop never terminates.
+pub struct InfiniteLoopAnalyzer {}
+
+impl Analyzer for InfiniteLoopAnalyzer {
+    type Results = Vec<Location>;
+
+    fn analyze(&self, code: &[Node]) -> Vec<Location> {
+        let mut found = vec![];
+        find_infinite_loops(code, Location::default(), &mut found);
+        found
+    }
+}
+
+fn find_infinite_loops(code: &[Node], start: Location, found: &mut Vec<Location>) {
+    let mut location = start;
+
+    for node in code {
+        if let Some((offset, body)) = node.as_loop() {
+            let stride = match *node {
+                Node::Loop(ref l) => l.stride,
+                _ => 0,
+            };
+
+            if stride == 0 && written_cells(body).is_some_and(|cells| !cells.contains(&offset)) {
+                found.push(location);
+            }
+            find_infinite_loops(body, location.next(), found);
+        }
+        location = location.after(node);
+    }
+}
+
+/// The cells a block may write relative to the data pointer, if the block always ends up where it
+/// started
+pub fn written_cells(code: &[Node]) -> Option<Vec<isize>> {
+    let mut cells = vec![];
+    let mut current = 0;
+
+    for node in code {
+        match *node {
+            Node::Shift(v) => current += v,
+            Node::Inc(_, offset, move_pointer)
+            | Node::Dec(_, offset, move_pointer)
+            | Node::Assign(_, offset, move_pointer)
+            | Node::In(offset, move_pointer) => {
+                cells.push(current + offset);
+                if move_pointer {
+                    current += offset;
+                }
+            }
+            Node::Out(offset, move_pointer) => {
+                if move_pointer {
+                    current += offset;
+                }
+            }
+            Node::Mul(_, into, offset, move_pointer) => {
+                cells.push(current + offset + into);
+                if move_pointer {
+                    current += offset;
+                }
+            }
+            Node::Product(_, into, _, _) => cells.push(current + into),
+            Node::Conditional(ref body) => {
+                cells.extend(written_cells(body)?.into_iter().map(|c| c + current));
+            }
+            Node::Loop(ref l) if l.stride == 0 => {
+                cells.extend(written_cells(&l.body)?.into_iter().map(|c| c + current));
+            }
+            Node::Comment(_) => {}
+            Node::Loop(_) | Node::Scan(_) => return None,
+        }
+    }
+
+    if current != 0 {
+        return None;
+    }
+
+    Some(cells)
+}
+
+/// A range of pointer positions relative to the start of the code, `isize::MIN` and `isize::MAX` stand
+/// for unbounded ends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interval {
+    pub min: isize,
+    pub max: isize,
+}
+
+impl Interval {
+    pub fn at(position: isize) -> Interval {
+        Interval {
+            min: position,
+            max: position,
+        }
+    }
+
+    pub fn is_bounded(&self) -> bool {
+        self.min != isize::MIN && self.max != isize::MAX
+    }
+
+    /// Moves both ends by `offset`, unbounded ends stay unbounded
+    fn shift(self, offset: isize) -> Interval {
+        let move_end = |end: isize| {
+            if end == isize::MIN || end == isize::MAX {
+                end
+            } else {
+                end.saturating_add(offset)
+            }
+        };
+
+        Interval {
+            min: move_end(self.min),
+            max: move_end(self.max),
+        }
+    }
+
+    fn join(self, other: Interval) -> Interval {
+        Interval {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    /// Drops the bounds `other` goes beyond, so repeatedly joining always ends up at a fixed point
+    fn widen(self, other: Interval) -> Interval {
+        Interval {
+            min: if other.min < self.min {
+                isize::MIN
+            } else {
+                self.min
+            },
+            max: if other.max > self.max {
+                isize::MAX
+            } else {
+                self.max
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PointerRanges {
+    /// The possible pointer positions before every node, nested nodes come right after their loop
+    pub nodes: Vec<Interval>,
+    /// Every cell the pointer may move to or the code may access
+    pub cells: Interval,
+}
+
+impl PointerRanges {
+    /// Whether code starting at `start` may move past an end of the tape and wrap around
+    pub fn reaches_edges(&self, start: usize) -> bool {
+        self.cells.min < -(start as isize) || self.cells.max >= (NUMBER_OF_CELLS - start) as isize
+    }
+}
+
+/// Computes the pointer positions code may reach, relative to where it starts. Balanced code gets exact
+/// ranges, the ranges of loops that move the pointer and scans are unbounded in the direction they
+/// move.
+pub struct PointerRangeAnalyzer {}
+
+impl Analyzer for PointerRangeAnalyzer {
+    type Results = PointerRanges;
+
+    fn analyze(&self, code: &[Node]) -> PointerRanges {
+        let mut results = PointerRanges {
+            nodes: vec![],
+            cells: Interval::at(0),
+        };
+        pointer_ranges(code, Interval::at(0), &mut 0, &mut results);
+        results
+    }
+}
+
+/// Records the ranges of a block starting at the `index`th node and returns the range after the block
+fn pointer_ranges(
+    code: &[Node],
+    entry: Interval,
+    index: &mut usize,
+    results: &mut PointerRanges,
+) -> Interval {
+    let mut current = entry;
+
+    for node in code {
+        if *index == results.nodes.len() {
+            results.nodes.push(current);
+        } else {
+            results.nodes[*index] = results.nodes[*index].join(current);
+        }
+        *index += 1;
+
+        let mut touched = vec![];
+        match *node {
+            Node::Shift(v) => current = current.shift(v),
+            Node::Inc(_, offset, move_pointer)
+            | Node::Dec(_, offset, move_pointer)
+            | Node::Assign(_, offset, move_pointer)
+            | Node::Out(offset, move_pointer)
+            | Node::In(offset, move_pointer) => {
+                touched.push(current.shift(offset));
+                if move_pointer {
+                    current = current.shift(offset);
+                }
+            }
+            Node::Mul(_, into, offset, move_pointer) => {
+                touched.push(current.shift(offset));
+                touched.push(current.shift(offset + into));
+                if move_pointer {
+                    current = current.shift(offset);
+                }
+            }
+            Node::Product(_, into, first, second) => {
+                touched.push(current.shift(into));
+                touched.push(current.shift(first));
+                touched.push(current.shift(second));
+            }
+            Node::Scan(interval) => {
+                current = current.widen(current.shift(interval.signum()));
+            }
+            Node::Conditional(_) | Node::Loop(_) => {
+                let (offset, body) = node.as_loop().unwrap();
+                let mut nested_index;
+
+                loop {
+                    results.cells = results.cells.join(current.shift(offset));
+                    nested_index = *index;
+
+                    let exit = pointer_ranges(body, current, &mut nested_index, results);
+                    if current.join(exit) == current {
+                        break;
+                    }
+                    current = current.widen(exit);
+                }
+                *index = nested_index;
+            }
+            Node::Comment(_) => {}
+        }
+
+        touched.push(current);
+        for interval in touched {
+            results.cells = results.cells.join(interval);
+        }
+    }
+
+    current
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LoopKind {
+    /// A loop at the start of the program, which never runs and is used for comments
+    Comment,
+    /// A loop that only changes the current cell, like `[-]`
+    Clear,
+    /// A balanced loop that only adds to cells, like `[->++<]`
+    Multiplication,
+    /// A loop that only moves the data pointer, like `[>]`
+    Scan,
+    /// Any other loop that ends up where it started
+    Balanced,
+    /// Any other loop that moves the data pointer
+    Unbalanced,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LoopOptimization {
+    CollapseAssignments,
+    CollapseSimpleLoops,
+    CollapseNestedLoops,
+    CollapseScanLoops,
+    NormalizeLoops,
+}
+
+/// Why no optimization handled a loop
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MissedReason {
+    NeverRuns,
+    Io,
+    UnknownStride,
+    NestedLoops,
+    EvenCounterStep,
+    IrregularCounter,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LoopHandling {
+    Optimized(LoopOptimization),
+    Missed(MissedReason),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LoopReport {
+    pub location: Location,
+    /// Loops at the top level have a depth of 1
+    pub depth: usize,
+    pub kind: LoopKind,
+    pub handling: LoopHandling,
+}
+
+impl fmt::Display for LoopReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let kind = match self.kind {
+            LoopKind::Comment => "comment loop",
+            LoopKind::Clear => "clear loop",
+            LoopKind::Multiplication => "multiplication loop",
+            LoopKind::Scan => "scan loop",
+            LoopKind::Balanced => "balanced loop",
+            LoopKind::Unbalanced => "unbalanced loop",
+        };
+        let handling = match self.handling {
+            LoopHandling::Optimized(optimization) => match optimization {
+                LoopOptimization::CollapseAssignments => "optimized by Collapse Assignments",
+                LoopOptimization::CollapseSimpleLoops => "optimized by Collapse Simple Loops",
+                LoopOptimization::CollapseNestedLoops => "optimized by Collapse Nested Loops",
+                LoopOptimization::CollapseScanLoops => "optimized by Collapse Scan Loops",
+                LoopOptimization::NormalizeLoops => "optimized by Normalize Loops",
+            },
+            LoopHandling::Missed(reason) => match reason {
+                MissedReason::NeverRuns => "not optimized, it never runs",
+                MissedReason::Io => "not optimized, it does I/O",
+                MissedReason::UnknownStride => {
+                    "not optimized, it moves the data pointer by a varying amount"
+                }
+                MissedReason::NestedLoops => "not optimized, its nested loops were not collapsed",
+                MissedReason::EvenCounterStep => {
+                    "not optimized, its counter changes by an even step"
+                }
+                MissedReason::IrregularCounter => {
+                    "not optimized, its counter does not change by a fixed step"
+                }
+            },
+        };
+
+        write!(
+            f,
+            "{}:{}: depth {} {}, {}",
+            self.location.line, self.location.column, self.depth, kind, handling
+        )
+    }
+}
+
+/// Classifies every loop in parsed code and reports how the optimizer handles it, loops are ordered by
+/// their location
+pub struct LoopAnalyzer {}
+
+impl Analyzer for LoopAnalyzer {
+    type Results = Vec<LoopReport>;
+
+    fn analyze(&self, code: &[Node]) -> Vec<LoopReport> {
+        let mut reports = vec![];
+        let first = code.iter().position(|n| !matches!(n, Node::Comment(_)));
+        let mut location = Location::default();
+
+        for (index, node) in code.iter().enumerate() {
+            if let Node::Conditional(body) = node {
+                if Some(index) == first {
+                    reports.push(LoopReport {
+                        location,
+                        depth: 1,
+                        kind: LoopKind::Comment,
+                        handling: LoopHandling::Missed(MissedReason::NeverRuns),
+                    });
+                } else {
+                    classify_loops(body, location, 1, &mut reports);
+                }
+            }
+            location = location.after(node);
+        }
+
+        reports
+    }
+}
+
+fn classify_loops(body: &[Node], location: Location, depth: usize, reports: &mut Vec<LoopReport>) {
+    let is_nested_loop = |n: &Node| matches!(n, Node::Conditional(_) | Node::Loop(_));
+    let is_io = |n: &Node| matches!(n, Node::In(_, _) | Node::Out(_, _));
+    let significant: Vec<&Node> = body
+        .iter()
+        .filter(|n| !matches!(n, Node::Comment(_)))
+        .collect();
+    let loop_stride = stride(body);
+
+    let kind = if significant.iter().all(|n| matches!(n, Node::Shift(_))) && loop_stride != Some(0)
+    {
+        LoopKind::Scan
+    } else if loop_stride != Some(0) {
+        LoopKind::Unbalanced
+    } else if significant
+        .iter()
+        .all(|n| matches!(n, Node::Inc(_, 0, false) | Node::Dec(_, 0, false)))
+        && counter_step(body).is_some_and(|step| step % 2 == 1)
+    {
+        LoopKind::Clear
+    } else if significant
+        .iter()
+        .all(|n| matches!(n, Node::Shift(_) | Node::Inc(_, _, _) | Node::Dec(_, _, _)))
+    {
+        LoopKind::Multiplication
+    } else {
+        LoopKind::Balanced
+    };
+
+    let optimized = optimize_code(
+        &[Node::Conditional(body.to_vec())],
+        &OptimizationOptions::default(),
+    );
+    let handling = match optimized_by(&optimized) {
+        Some(optimization) => LoopHandling::Optimized(optimization),
+        None if contains(body, &is_io) => LoopHandling::Missed(MissedReason::Io),
+        None if loop_stride.is_none() => LoopHandling::Missed(MissedReason::UnknownStride),
+        None if optimized.iter().any(|n| match n {
+            Node::Conditional(inner) => contains(inner, &is_nested_loop),
+            _ => false,
+        }) =>
+        {
+            LoopHandling::Missed(MissedReason::NestedLoops)
+        }
+        None => match counter_step(body) {
+            Some(step) if step % 2 == 0 => LoopHandling::Missed(MissedReason::EvenCounterStep),
+            _ => LoopHandling::Missed(MissedReason::IrregularCounter),
+        },
+    };
+
+    reports.push(LoopReport {
+        location,
+        depth,
+        kind,
+        handling,
+    });
+
+    let mut nested_location = location.next();
+    for node in body {
+        if let Node::Conditional(nested) = node {
+            classify_loops(nested, nested_location, depth + 1, reports);
+        }
+        nested_location = nested_location.after(node);
+    }
+}
+
+/// Whether any node in some code, including nested nodes, matches a predicate
+fn contains(code: &[Node], predicate: &dyn Fn(&Node) -> bool) -> bool {
+    code.iter().any(|n| {
+        predicate(n)
+            || n.as_loop()
+                .is_some_and(|(_, body)| contains(body, predicate))
+    })
+}
+
+/// How much a loop body changes the cell its loop checks, if only additions and subtractions change it
+fn counter_step(body: &[Node]) -> Option<u8> {
+    let mut current = 0;
+    let mut step: u8 = 0;
+
+    for node in body {
+        match *node {
+            Node::Shift(v) => current += v,
+            Node::Inc(v, offset, _) if current + offset == 0 => step = step.wrapping_add(v),
+            Node::Dec(v, offset, _) if current + offset == 0 => step = step.wrapping_sub(v),
+            Node::Inc(_, offset, move_pointer) | Node::Dec(_, offset, move_pointer) => {
+                if move_pointer {
+                    current += offset;
+                }
+            }
+            Node::Out(_, false) | Node::Comment(_) => {}
+            _ => return None,
+        }
+    }
+
+    Some(step)
+}
+
+/// Which optimization turned a loop into straight-line code or annotated it, given the optimized loop
+fn optimized_by(optimized: &[Node]) -> Option<LoopOptimization> {
+    let runs_once = |body: &[Node]| {
+        body.last() == Some(&Node::Assign(0, 0, false))
+            && !body.iter().any(|n| {
+                matches!(
+                    n,
+                    Node::Conditional(_) | Node::Loop(_) | Node::In(_, _) | Node::Out(_, _)
+                )
+            })
+    };
+    let mut nodes = vec![];
+
+    for node in optimized {
+        match node {
+            Node::Loop(_) => return Some(LoopOptimization::NormalizeLoops),
+            Node::Conditional(body) if runs_once(body) => nodes.extend(body.iter()),
+            Node::Conditional(_) => return None,
+            n => nodes.push(n),
+        }
+    }
+
+    if nodes.iter().any(|n| matches!(n, Node::Scan(_))) {
+        Some(LoopOptimization::CollapseScanLoops)
+    } else if nodes.iter().any(|n| matches!(n, Node::Product(_, _, _, _))) {
+        Some(LoopOptimization::CollapseNestedLoops)
+    } else if nodes.iter().any(|n| matches!(n, Node::Mul(_, _, _, _))) {
+        Some(LoopOptimization::CollapseSimpleLoops)
+    } else {
+        Some(LoopOptimization::CollapseAssignments)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use parser::parse_code;
+    use vm::{run_block_traced, State};
 
     #[test]
     fn it_should_return_empty_results() {
@@ -94,7 +748,7 @@ mod tests {
         let result = analyzer.analyze(&code);
         let mut expected_nodes = HashMap::new();
 
-        expected_nodes.insert(Node::Shift(0), 1);
+        expected_nodes.insert(InstructionKind::Shift, 1);
 
         assert_eq!(
             result,
@@ -117,9 +771,9 @@ mod tests {
         let result = analyzer.analyze(&code);
         let mut expected_nodes = HashMap::new();
 
-        expected_nodes.insert(Node::Shift(0), 2);
-        expected_nodes.insert(Node::Mul(0, 0, 0, false), 1);
-        expected_nodes.insert(Node::Inc(0, 0, false), 1);
+        expected_nodes.insert(InstructionKind::Shift, 2);
+        expected_nodes.insert(InstructionKind::Mul, 1);
+        expected_nodes.insert(InstructionKind::Inc, 1);
 
         assert_eq!(
             result,
@@ -137,7 +791,7 @@ mod tests {
         let result = analyzer.analyze(&code);
         let mut expected_nodes = HashMap::new();
 
-        expected_nodes.insert(Node::Conditional(vec![]), 1);
+        expected_nodes.insert(InstructionKind::Conditional, 1);
 
         assert_eq!(
             result,
@@ -158,9 +812,9 @@ mod tests {
         let result = analyzer.analyze(&code);
         let mut expected_nodes = HashMap::new();
 
-        expected_nodes.insert(Node::Conditional(vec![]), 2);
-        expected_nodes.insert(Node::Shift(0), 2);
-        expected_nodes.insert(Node::Inc(0, 0, false), 1);
+        expected_nodes.insert(InstructionKind::Conditional, 2);
+        expected_nodes.insert(InstructionKind::Shift, 2);
+        expected_nodes.insert(InstructionKind::Inc, 1);
 
         assert_eq!(
             result,
@@ -170,4 +824,330 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn it_should_find_loops_that_never_change_their_condition() {
+        let sources = [
+            ("+[]", vec![Location { line: 1, column: 2 }]),
+            ("+[>+<]", vec![Location { line: 1, column: 2 }]),
+            (
+                "+[>[-]<]\n >+[.>,<]",
+                vec![
+                    Location { line: 1, column: 2 },
+                    Location { line: 2, column: 4 },
+                ],
+            ),
+            ("+[[>]+<]", vec![]),
+            ("+[-]+[>]+[<,>,]", vec![]),
+            ("+[>[<]>]", vec![]),
+        ];
+        let analyzer = InfiniteLoopAnalyzer {};
+
+        for (source, expected) in sources.iter() {
+            let parsed = parse_code(&mut source.as_bytes()).unwrap();
+
+            assert_eq!(&analyzer.analyze(&parsed), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn it_should_find_infinite_loops_nested_in_other_loops() {
+        let parsed = parse_code(&mut "+[>+[>+<]<-]".as_bytes()).unwrap();
+        let analyzer = InfiniteLoopAnalyzer {};
+
+        assert_eq!(
+            analyzer.analyze(&parsed),
+            vec![Location { line: 1, column: 5 }]
+        );
+    }
+
+    #[test]
+    fn it_should_compute_exact_ranges_for_balanced_code() {
+        let parsed = parse_code(&mut ">>+[<-<+>>]<".as_bytes()).unwrap();
+        let analyzer = PointerRangeAnalyzer {};
+        let result = analyzer.analyze(&parsed);
+
+        assert_eq!(
+            result.nodes,
+            [0, 1, 2, 2, 2, 1, 1, 0, 0, 1, 2]
+                .iter()
+                .map(|&p| Interval::at(p))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(result.cells, Interval { min: 0, max: 2 });
+        assert!(!result.reaches_edges(0));
+    }
+
+    #[test]
+    fn it_should_widen_ranges_of_unbalanced_loops() {
+        let parsed = parse_code(&mut "+[>+]<<".as_bytes()).unwrap();
+        let analyzer = PointerRangeAnalyzer {};
+        let result = analyzer.analyze(&parsed);
+
+        assert_eq!(
+            result.nodes,
+            vec![
+                Interval::at(0),
+                Interval::at(0),
+                Interval {
+                    min: 0,
+                    max: isize::MAX
+                },
+                Interval {
+                    min: 1,
+                    max: isize::MAX
+                },
+                Interval {
+                    min: 0,
+                    max: isize::MAX
+                },
+                Interval {
+                    min: -1,
+                    max: isize::MAX
+                },
+            ]
+        );
+        assert!(!result.cells.is_bounded());
+        assert!(result.reaches_edges(100));
+    }
+
+    #[test]
+    fn it_should_widen_ranges_of_scans() {
+        let code = vec![Node::Shift(3), Node::Scan(-2), Node::Inc(1, 1, false)];
+        let analyzer = PointerRangeAnalyzer {};
+        let result = analyzer.analyze(&code);
+
+        assert_eq!(
+            result.nodes[2],
+            Interval {
+                min: isize::MIN,
+                max: 3
+            }
+        );
+        assert_eq!(
+            result.cells,
+            Interval {
+                min: isize::MIN,
+                max: 4
+            }
+        );
+    }
+
+    #[test]
+    fn it_should_include_offsets_in_the_accessed_cells() {
+        let code = vec![
+            Node::Mul(2, 3, -1, false),
+            Node::new_loop(2, 0, vec![Node::Assign(0, 2, false)]),
+        ];
+        let analyzer = PointerRangeAnalyzer {};
+        let result = analyzer.analyze(&code);
+
+        assert_eq!(result.cells, Interval { min: -1, max: 2 });
+        assert!(result.reaches_edges(0));
+        assert!(!result.reaches_edges(1));
+        assert!(result.reaches_edges(NUMBER_OF_CELLS - 2));
+    }
+
+    #[test]
+    fn it_should_classify_loops() {
+        let code = "[comment [loop]]+[-]>[>]<\n[->++<]+[>+]+[[-]>+<-]+[.-]";
+        let parsed = parse_code(&mut code.as_bytes()).unwrap();
+        let analyzer = LoopAnalyzer {};
+        let result: Vec<(usize, usize, usize, LoopKind)> = analyzer
+            .analyze(&parsed)
+            .iter()
+            .map(|r| (r.location.line, r.location.column, r.depth, r.kind))
+            .collect();
+
+        assert_eq!(
+            result,
+            vec![
+                (1, 1, 1, LoopKind::Comment),
+                (1, 18, 1, LoopKind::Clear),
+                (1, 22, 1, LoopKind::Scan),
+                (2, 1, 1, LoopKind::Multiplication),
+                (2, 9, 1, LoopKind::Unbalanced),
+                (2, 14, 1, LoopKind::Balanced),
+                (2, 15, 2, LoopKind::Clear),
+                (2, 24, 1, LoopKind::Balanced),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_should_report_how_loops_are_optimized() {
+        let sources = [
+            ("[.]", LoopHandling::Missed(MissedReason::NeverRuns)),
+            (
+                "+[-]",
+                LoopHandling::Optimized(LoopOptimization::CollapseAssignments),
+            ),
+            (
+                "+[->+<]",
+                LoopHandling::Optimized(LoopOptimization::CollapseSimpleLoops),
+            ),
+            (
+                "+[->[->+>+<<]>[-<+>]<<]",
+                LoopHandling::Optimized(LoopOptimization::CollapseNestedLoops),
+            ),
+            (
+                "+[>>]",
+                LoopHandling::Optimized(LoopOptimization::CollapseScanLoops),
+            ),
+            (
+                "+[>+>]",
+                LoopHandling::Optimized(LoopOptimization::NormalizeLoops),
+            ),
+            ("+[.-]", LoopHandling::Missed(MissedReason::Io)),
+            (
+                "+[>[>]+<]",
+                LoopHandling::Missed(MissedReason::UnknownStride),
+            ),
+            ("+[>,[-<+>]<]", LoopHandling::Missed(MissedReason::Io)),
+            (
+                "+[>[--]<-]",
+                LoopHandling::Missed(MissedReason::NestedLoops),
+            ),
+            (
+                "+[-->+<]",
+                LoopHandling::Missed(MissedReason::EvenCounterStep),
+            ),
+            (
+                "+[>[-<+>]<-]",
+                LoopHandling::Missed(MissedReason::IrregularCounter),
+            ),
+        ];
+        let analyzer = LoopAnalyzer {};
+
+        for (source, expected) in sources.iter() {
+            let parsed = parse_code(&mut source.as_bytes()).unwrap();
+
+            assert_eq!(
+                analyzer.analyze(&parsed)[0].handling,
+                *expected,
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn it_should_format_loop_reports() {
+        let report = LoopReport {
+            location: Location { line: 2, column: 3 },
+            depth: 2,
+            kind: LoopKind::Multiplication,
+            handling: LoopHandling::Optimized(LoopOptimization::CollapseSimpleLoops),
+        };
+
+        assert_eq!(
+            format!("{}", report),
+            "2:3: depth 2 multiplication loop, optimized by Collapse Simple Loops"
+        );
+    }
+
+    #[test]
+    fn it_should_count_input_and_output_separately() {
+        let code = vec![Node::In(0, false), Node::Out(0, false), Node::Out(1, true)];
+        let result = SimpleAnalyzer {}.analyze(&code);
+
+        assert_eq!(result.total(), 3);
+        assert_eq!(result.count(InstructionKind::In), 1);
+        assert_eq!(result.count(InstructionKind::Out), 2);
+        assert_eq!(result.count(InstructionKind::Shift), 0);
+    }
+
+    #[test]
+    fn it_should_serialize_results_to_json() {
+        let code = vec![
+            Node::Shift(1),
+            Node::Conditional(vec![Node::Dec(1, 0, false), Node::Shift(2)]),
+        ];
+        let result = SimpleAnalyzer {}.analyze(&code);
+
+        assert_eq!(
+            result.to_json(),
+            "{\"total\":4,\"nodes\":{\"shift\":2,\"inc\":0,\"dec\":1,\"mul\":0,\"product\":0,\
+             \"assign\":0,\"scan\":0,\"out\":0,\"in\":0,\"conditional\":1,\"loop\":0,\"comment\":0}}"
+        );
+    }
+
+    #[test]
+    fn it_should_format_results_as_a_table() {
+        let code = vec![
+            Node::Shift(1),
+            Node::Shift(2),
+            Node::Inc(1, 0, false),
+            Node::Out(0, false),
+        ];
+        let result = SimpleAnalyzer {}.analyze(&code);
+
+        assert_eq!(
+            format!("{}", result),
+            "Instruction    Count   Share\n\
+             shift              2   50.0%\n\
+             inc                1   25.0%\n\
+             out                1   25.0%\n\
+             total              4"
+        );
+    }
+
+    #[test]
+    fn it_should_compare_unoptimized_and_optimized_code() {
+        let parsed = parse_code(&mut "++>+<[->+<]".as_bytes()).unwrap();
+        let optimized = optimize_code(&parsed, &OptimizationOptions::default());
+        let comparison = Comparison::new(&parsed, &optimized);
+
+        assert_eq!(comparison.unoptimized.total(), 10);
+        assert_eq!(comparison.reduction(InstructionKind::Inc), Some(50.0));
+        assert_eq!(comparison.reduction(InstructionKind::Dec), Some(100.0));
+        assert_eq!(comparison.reduction(InstructionKind::Mul), None);
+        assert!(comparison.total_reduction().unwrap() > 50.0);
+    }
+
+    #[test]
+    fn it_should_profile_executed_nodes() {
+        let code = vec![
+            Node::Inc(3, 0, false),
+            Node::Conditional(vec![Node::Dec(1, 0, false), Node::Out(0, false)]),
+        ];
+        let mut profile = Profile::default();
+        let mut stdout = vec![];
+
+        run_block_traced(
+            &mut [].as_ref(),
+            &mut stdout,
+            &code,
+            &mut State::default(),
+            &mut profile,
+        )
+        .unwrap();
+
+        assert_eq!(profile.count(InstructionKind::Inc), 1);
+        assert_eq!(profile.count(InstructionKind::Conditional), 1);
+        assert_eq!(profile.count(InstructionKind::Dec), 3);
+        assert_eq!(profile.count(InstructionKind::Out), 3);
+        assert_eq!(profile.total(), 8);
+    }
+
+    #[test]
+    fn it_should_format_comparisons_as_a_table() {
+        let comparison = Comparison {
+            unoptimized: SimpleAnalyzer {}.analyze(&[
+                Node::Shift(1),
+                Node::Shift(1),
+                Node::Out(0, false),
+            ]),
+            optimized: SimpleAnalyzer {}.analyze(&[Node::Out(2, true)]),
+            profile: Some(Profile::default()),
+        };
+
+        assert_eq!(
+            format!("{}", comparison),
+            "Instruction  Unoptimized Optimized  Reduction      Executed\n\
+             shift                  2         0     100.0%             0\n\
+             out                    1         1       0.0%             0\n\
+             total                  3         1      66.7%             0\n"
+        );
+    }
 }
@@ -0,0 +1,680 @@
+use std::fmt;
+
+use ruinfuck::encoding::DecodeError;
+use ruinfuck::format::FormatOptions;
+use ruinfuck::parser::{Location, ParserError};
+use ruinfuck::view::{CellFormat, TapeView};
+use ruinfuck::vm::{EofPolicy, RuntimeError, NUMBER_OF_CELLS};
+
+pub const USAGE: &str = "Usage: ruinfuck [<command>] [<options>] [<file> | - | -e <code>]
+
+Commands:
+    run        Run a program (the default when a file is given)
+    repl       Start the interactive REPL (the default without arguments)
+    compile    Write the optimized program in binary form, or as IR text with --ir
+    analyze    Compare the program before and after optimizing it
+    fmt        Format the source of a program
+    debug      Run a program and show the tape when it stops
+    bench      Run a program repeatedly and report how long it takes
+
+Options:
+    -e <code>              Run the given code instead of a file
+    -                      Read the program from stdin
+    -i, --input <file>     Read the input of the program from a file instead of stdin
+    -O<level>              Optimization level from 0 to 2, defaults to 2
+    --eof <policy>         What reading does at the end of the input: error, zero, minus-one or unchanged
+    --tape <storage>       How the tape is stored: array (fixed size, the default), heap or sparse,
+                           which only allocates the parts of the tape that are used
+    --tape-size <cells>    Number of cells on the tape, defaults to 65536. Other sizes use the heap
+                           tape unless --tape says otherwise
+    --cell-width <bits>    Width of the cells: 8 (the default), 16 or 32. Wider cells use the heap
+                           tape unless --tape says otherwise, and write their lowest byte as output
+    -o <file>              Where `compile` and `fmt` write the program, defaults to stdout
+    --ir                   Make `compile` print the optimized program as IR text
+    --run-profile          Make `analyze` run the program with the input from --input, or no
+                           input at all, and count the executed instructions
+    --lint                 Make `analyze` also report suspicious code
+    --loops                Make `analyze` also classify every loop
+    --indent <spaces>      How far `fmt` indents loop bodies, defaults to 4
+    --width <columns>      The column `fmt` wraps lines at, lines are not wrapped by default
+    --minify               Make `fmt` write the shortest source that does the same on an empty tape
+    --trace                Make `debug` print every instruction it executes
+    --no-dump              Make `repl` only show the state with :state
+    --cells <format>       Show cells in `debug` and `repl` as decimal, hex or ascii
+    --window <cells>       Number of cells `debug` and `repl` show, defaults to 25
+    --center <pos>         Show the cells around a position instead of the data pointer
+    --highlight            Show cells that are not zero in bold
+    --compact              Show all cells that are not zero instead of a window
+    -n <iterations>        How often `bench` runs the program, defaults to 10. Every run reads
+                           the input file given with --input, or no input at all
+    -h, --help             Show this help
+
+Exit codes:
+    1    The program failed while running, like a loop that never terminates
+    2    The command line is invalid
+    3    The program could not be parsed or loaded
+    4    A file, the input or the output could not be read or written
+    5    A bug in ruinfuck itself";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Run,
+    Repl,
+    Compile,
+    Analyze,
+    Fmt,
+    Debug,
+    Bench,
+    Help,
+}
+
+impl Command {
+    fn from_name(name: &str) -> Option<Command> {
+        match name {
+            "run" => Some(Command::Run),
+            "repl" => Some(Command::Repl),
+            "compile" => Some(Command::Compile),
+            "analyze" => Some(Command::Analyze),
+            "fmt" => Some(Command::Fmt),
+            "debug" => Some(Command::Debug),
+            "bench" => Some(Command::Bench),
+            "help" => Some(Command::Help),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Command::Run => "run",
+            Command::Repl => "repl",
+            Command::Compile => "compile",
+            Command::Analyze => "analyze",
+            Command::Fmt => "fmt",
+            Command::Debug => "debug",
+            Command::Bench => "bench",
+            Command::Help => "help",
+        }
+    }
+
+    /// Whether the command works on a program given as a file, on stdin or inline
+    fn takes_program(self) -> bool {
+        !matches!(self, Command::Repl | Command::Help)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    File(String),
+    Stdin,
+    Inline(String),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Source::File(path) => f.write_str(path),
+            Source::Stdin => f.write_str("<stdin>"),
+            Source::Inline(_) => f.write_str("<inline>"),
+        }
+    }
+}
+
+/// Everything that makes a command fail, every category has its own exit code
+#[derive(Debug, PartialEq)]
+pub enum CliError {
+    Usage(String),
+    Io(String),
+    Parse(Source, ParserError),
+    Decode(Source, DecodeError),
+    Run(Source, RuntimeError),
+    /// A bug in ruinfuck itself
+    Internal(String),
+}
+
+impl CliError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CliError::Run(_, RuntimeError::InfiniteLoop(_)) => 1,
+            CliError::Usage(_) => 2,
+            CliError::Parse(_, _) | CliError::Decode(_, _) => 3,
+            // The program could not read its input or write its output
+            CliError::Io(_) | CliError::Run(_, _) => 4,
+            CliError::Internal(_) => 5,
+        }
+    }
+}
+
+/// Prefixes a message with the source it is about, and the location in the source if there is one
+fn write_located(
+    f: &mut fmt::Formatter,
+    source: &Source,
+    location: Option<Location>,
+    message: &dyn fmt::Display,
+) -> Result<(), fmt::Error> {
+    match location {
+        Some(_) => write!(f, "{}:{}", source, message),
+        None => write!(f, "{}: {}", source, message),
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            CliError::Usage(e) | CliError::Io(e) | CliError::Internal(e) => f.write_str(e),
+            CliError::Parse(source, e) => write_located(f, source, e.location(), e),
+            CliError::Decode(source, e) => write_located(f, source, None, e),
+            CliError::Run(source, e) => write_located(f, source, e.location(), e),
+        }
+    }
+}
+
+/// The storage of the tape, see `ruinfuck::tape`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TapeKind {
+    Array,
+    Heap,
+    Sparse,
+}
+
+/// The type of the cells, see `ruinfuck::tape::CellValue`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CellWidth {
+    U8,
+    U16,
+    U32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub source: Option<Source>,
+    pub input: Option<String>,
+    pub level: u8,
+    pub eof: EofPolicy,
+    /// The tape storage, chosen by the tape size if `None`
+    pub tape: Option<TapeKind>,
+    pub tape_size: usize,
+    pub cell_width: CellWidth,
+    pub output: Option<String>,
+    pub ir: bool,
+    pub run_profile: bool,
+    pub lint: bool,
+    pub loops: bool,
+    pub trace: bool,
+    pub iterations: usize,
+    pub no_dump: bool,
+    pub view: TapeView,
+    pub format: FormatOptions,
+    pub minify: bool,
+}
+
+impl Options {
+    pub fn tape_kind(&self) -> TapeKind {
+        match self.tape {
+            Some(kind) => kind,
+            None if self.tape_size == NUMBER_OF_CELLS && self.cell_width == CellWidth::U8 => {
+                TapeKind::Array
+            }
+            None => TapeKind::Heap,
+        }
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            source: None,
+            input: None,
+            level: 2,
+            eof: EofPolicy::Error,
+            tape: None,
+            tape_size: NUMBER_OF_CELLS,
+            cell_width: CellWidth::U8,
+            output: None,
+            ir: false,
+            run_profile: false,
+            lint: false,
+            loops: false,
+            trace: false,
+            iterations: 10,
+            no_dump: false,
+            view: TapeView::default(),
+            format: FormatOptions::default(),
+            minify: false,
+        }
+    }
+}
+
+/// Parses the command line arguments, without the name of the binary
+pub fn parse_args(args: &[String]) -> Result<(Command, Options), String> {
+    let mut options = Options::default();
+    let mut args = args.iter().peekable();
+
+    let command = match args.peek().map(|a| a.as_str()) {
+        None => return Ok((Command::Repl, options)),
+        Some(name) => match Command::from_name(name) {
+            Some(command) => {
+                args.next();
+                command
+            }
+            None => Command::Run,
+        },
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| {
+            args.next()
+                .cloned()
+                .ok_or_else(|| format!("`{}` needs a value", flag))
+        };
+        let only_for = |commands: &[Command]| {
+            if commands.contains(&command) {
+                Ok(())
+            } else {
+                Err(format!(
+                    "`{}` is not an option of `{}`",
+                    arg,
+                    command.name()
+                ))
+            }
+        };
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok((Command::Help, options)),
+            "-e" => set_source(&mut options, command, Source::Inline(value("-e")?))?,
+            "-" => set_source(&mut options, command, Source::Stdin)?,
+            "-i" | "--input" => options.input = Some(value(arg)?),
+            "--eof" => {
+                options.eof = match value(arg)?.as_str() {
+                    "error" => EofPolicy::Error,
+                    "zero" => EofPolicy::Zero,
+                    "minus-one" => EofPolicy::MinusOne,
+                    "unchanged" => EofPolicy::Unchanged,
+                    policy => return Err(format!("Unknown EOF policy `{}`", policy)),
+                }
+            }
+            "--tape" => {
+                only_for(&[
+                    Command::Run,
+                    Command::Analyze,
+                    Command::Debug,
+                    Command::Bench,
+                ])?;
+                options.tape = match value(arg)?.as_str() {
+                    "array" => Some(TapeKind::Array),
+                    "heap" => Some(TapeKind::Heap),
+                    "sparse" => Some(TapeKind::Sparse),
+                    tape => return Err(format!("Unknown tape `{}`", tape)),
+                }
+            }
+            "--tape-size" => {
+                let size = value(arg)?;
+                options.tape_size = size
+                    .parse()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| format!("Invalid tape size `{}`", size))?;
+            }
+            "--cell-width" => {
+                only_for(&[
+                    Command::Run,
+                    Command::Analyze,
+                    Command::Debug,
+                    Command::Bench,
+                ])?;
+                options.cell_width = match value(arg)?.as_str() {
+                    "8" => CellWidth::U8,
+                    "16" => CellWidth::U16,
+                    "32" => CellWidth::U32,
+                    width => return Err(format!("Unsupported cell width `{}`", width)),
+                }
+            }
+            "-o" => {
+                only_for(&[Command::Compile, Command::Fmt])?;
+                options.output = Some(value(arg)?);
+            }
+            "--ir" => {
+                only_for(&[Command::Compile])?;
+                options.ir = true;
+            }
+            "--run-profile" | "--lint" | "--loops" => {
+                only_for(&[Command::Analyze])?;
+                match arg.as_str() {
+                    "--run-profile" => options.run_profile = true,
+                    "--lint" => options.lint = true,
+                    _ => options.loops = true,
+                }
+            }
+            "--indent" | "--width" => {
+                only_for(&[Command::Fmt])?;
+                let number = value(arg)?;
+                let parsed = number
+                    .parse()
+                    .ok()
+                    .filter(|n| *n > 0 || arg == "--indent")
+                    .ok_or_else(|| format!("Invalid value `{}` for `{}`", number, arg))?;
+                if arg == "--indent" {
+                    options.format.indent = parsed;
+                } else {
+                    options.format.width = Some(parsed);
+                }
+            }
+            "--minify" => {
+                only_for(&[Command::Fmt])?;
+                options.minify = true;
+            }
+            "--trace" => {
+                only_for(&[Command::Debug])?;
+                options.trace = true;
+            }
+            "--cells" => {
+                only_for(&[Command::Debug, Command::Repl])?;
+                options.view.format = match value(arg)?.as_str() {
+                    "decimal" => CellFormat::Decimal,
+                    "hex" => CellFormat::Hex,
+                    "ascii" => CellFormat::Ascii,
+                    format => return Err(format!("Unknown cell format `{}`", format)),
+                }
+            }
+            "--window" | "--center" => {
+                only_for(&[Command::Debug, Command::Repl])?;
+                let number = value(arg)?;
+                let parsed = number
+                    .parse()
+                    .ok()
+                    .filter(|n| *n > 0 || arg == "--center")
+                    .ok_or_else(|| format!("Invalid value `{}` for `{}`", number, arg))?;
+                if arg == "--window" {
+                    options.view.window = parsed;
+                } else {
+                    options.view.center = Some(parsed);
+                }
+            }
+            "--highlight" | "--compact" => {
+                only_for(&[Command::Debug, Command::Repl])?;
+                if arg == "--highlight" {
+                    options.view.highlight = true;
+                } else {
+                    options.view.compact = true;
+                }
+            }
+            "--no-dump" => {
+                only_for(&[Command::Repl])?;
+                options.no_dump = true;
+            }
+            "-n" => {
+                only_for(&[Command::Bench])?;
+                let iterations = value(arg)?;
+                options.iterations = iterations
+                    .parse()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .ok_or_else(|| format!("Invalid number of iterations `{}`", iterations))?;
+            }
+            level if level.starts_with("-O") => {
+                options.level = level[2..]
+                    .parse()
+                    .ok()
+                    .filter(|l| *l <= 2)
+                    .ok_or_else(|| format!("Invalid optimization level `{}`", level))?;
+            }
+            flag if flag.starts_with('-') => return Err(format!("Unknown option `{}`", flag)),
+            path => set_source(&mut options, command, Source::File(path.to_string()))?,
+        }
+    }
+
+    if command.takes_program() && options.source.is_none() {
+        return Err(format!("`{}` needs a program", command.name()));
+    }
+    if options.tape_size != NUMBER_OF_CELLS
+        && (command == Command::Repl || options.tape == Some(TapeKind::Array))
+    {
+        return Err(format!(
+            "A tape size of `{}` is not supported, the array tape always has {} cells",
+            options.tape_size, NUMBER_OF_CELLS
+        ));
+    }
+    if options.cell_width != CellWidth::U8 && options.tape == Some(TapeKind::Array) {
+        return Err(
+            "Wider cells are not supported, the array tape always has 8-bit cells".to_string(),
+        );
+    }
+    // Both depend on the tape size, which may come after them
+    if options.view.window >= options.tape_size {
+        return Err(format!(
+            "A window of `{}` cells does not fit on a tape with {} cells",
+            options.view.window, options.tape_size
+        ));
+    }
+    if let Some(center) = options.view.center.filter(|c| *c >= options.tape_size) {
+        return Err(format!(
+            "The center `{}` is outside of the tape with {} cells",
+            center, options.tape_size
+        ));
+    }
+
+    Ok((command, options))
+}
+
+fn set_source(options: &mut Options, command: Command, source: Source) -> Result<(), String> {
+    if !command.takes_program() {
+        return Err(format!("`{}` does not take a program", command.name()));
+    }
+    if options.source.is_some() {
+        return Err("Only one program can be given".to_string());
+    }
+
+    options.source = Some(source);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<(Command, Options), String> {
+        let args: Vec<String> = args.split_whitespace().map(|a| a.to_string()).collect();
+        parse_args(&args)
+    }
+
+    #[test]
+    fn it_should_keep_the_defaults_without_subcommands() {
+        assert_eq!(parse(""), Ok((Command::Repl, Options::default())));
+        assert_eq!(
+            parse("fuck/hello.fuck"),
+            Ok((
+                Command::Run,
+                Options {
+                    source: Some(Source::File("fuck/hello.fuck".to_string())),
+                    ..Options::default()
+                }
+            ))
+        );
+    }
+
+    #[test]
+    fn it_should_parse_subcommands_with_options() {
+        assert_eq!(
+            parse("run -O1 --eof zero -i input.txt -e +++."),
+            Ok((
+                Command::Run,
+                Options {
+                    source: Some(Source::Inline("+++.".to_string())),
+                    input: Some("input.txt".to_string()),
+                    level: 1,
+                    eof: EofPolicy::Zero,
+                    ..Options::default()
+                }
+            ))
+        );
+        assert_eq!(
+            parse("compile --ir -o out.ir - --tape-size 65536"),
+            Ok((
+                Command::Compile,
+                Options {
+                    source: Some(Source::Stdin),
+                    output: Some("out.ir".to_string()),
+                    ir: true,
+                    ..Options::default()
+                }
+            ))
+        );
+        assert_eq!(
+            parse("bench -n 3 a.fuck").map(|(c, o)| (c, o.iterations)),
+            Ok((Command::Bench, 3))
+        );
+        assert_eq!(
+            parse("repl -O0").map(|(c, o)| (c, o.level)),
+            Ok((Command::Repl, 0))
+        );
+        assert_eq!(parse("run --help").map(|(c, _)| c), Ok(Command::Help));
+        assert_eq!(
+            parse("fmt --indent 2 --width 80 -o out.fuck a.fuck").map(|(_, o)| o.format),
+            Ok(FormatOptions {
+                indent: 2,
+                width: Some(80),
+            })
+        );
+        assert_eq!(
+            parse("fmt --minify a.fuck").map(|(_, o)| o.minify),
+            Ok(true)
+        );
+        assert_eq!(
+            parse("analyze --run-profile a.fuck").map(|(_, o)| (o.run_profile, o.input)),
+            Ok((true, None))
+        );
+        assert_eq!(
+            parse("run --tape-size 100 a.fuck").map(|(_, o)| o.tape_kind()),
+            Ok(TapeKind::Heap)
+        );
+        assert_eq!(
+            parse("bench --tape sparse --tape-size 1000000000000 a.fuck")
+                .map(|(_, o)| (o.tape_kind(), o.tape_size)),
+            Ok((TapeKind::Sparse, 1_000_000_000_000))
+        );
+        assert_eq!(
+            parse("run --cell-width 16 a.fuck").map(|(_, o)| (o.tape_kind(), o.cell_width)),
+            Ok((TapeKind::Heap, CellWidth::U16))
+        );
+        assert_eq!(
+            parse("debug --cell-width 32 --tape sparse a.fuck")
+                .map(|(_, o)| (o.tape_kind(), o.cell_width)),
+            Ok((TapeKind::Sparse, CellWidth::U32))
+        );
+        assert_eq!(
+            parse("debug --cells ascii --window 9 --center 100 --compact a.fuck")
+                .map(|(_, o)| o.view),
+            Ok(TapeView {
+                format: CellFormat::Ascii,
+                window: 9,
+                center: Some(100),
+                compact: true,
+                ..TapeView::default()
+            })
+        );
+        assert_eq!(
+            parse("debug --center 70000 --tape-size 1000000 a.fuck").map(|(_, o)| o.view.center),
+            Ok(Some(70_000))
+        );
+    }
+
+    #[test]
+    fn it_should_report_errors_with_their_source_and_exit_code() {
+        let parse = CliError::Parse(
+            Source::File("a.fuck".to_string()),
+            ParserError::UnmatchedDelimiter(Location { line: 2, column: 3 }),
+        );
+        let run = CliError::Run(
+            Source::Inline("+[]".to_string()),
+            RuntimeError::WriteError("broken pipe".to_string()),
+        );
+        let decode = CliError::Decode(Source::Stdin, DecodeError::UnexpectedEnd);
+        let infinite_loop = CliError::Run(
+            Source::Stdin,
+            RuntimeError::InfiniteLoop(Location { line: 1, column: 2 }),
+        );
+
+        assert_eq!(
+            format!("{}", parse),
+            "a.fuck:2:3: this `]` has no matching `[`"
+        );
+        assert_eq!(
+            format!("{}", run),
+            "<inline>: could not write output: broken pipe"
+        );
+        assert_eq!(
+            format!("{}", decode),
+            "<stdin>: the compiled program ends unexpectedly"
+        );
+        assert_eq!(
+            [
+                parse,
+                run,
+                decode,
+                infinite_loop,
+                CliError::Io("".to_string()),
+                CliError::Internal("".to_string())
+            ]
+            .iter()
+            .map(|e| e.exit_code())
+            .collect::<Vec<_>>(),
+            vec![3, 4, 3, 1, 4, 5]
+        );
+    }
+
+    #[test]
+    fn it_should_reject_invalid_arguments() {
+        assert_eq!(
+            parse("run --run-profile a.fuck"),
+            Err("`--run-profile` is not an option of `run`".to_string())
+        );
+        assert_eq!(parse("run"), Err("`run` needs a program".to_string()));
+        assert_eq!(
+            parse("run a.fuck b.fuck"),
+            Err("Only one program can be given".to_string())
+        );
+        assert_eq!(
+            parse("run -O3 a.fuck"),
+            Err("Invalid optimization level `-O3`".to_string())
+        );
+        assert_eq!(parse("run -i"), Err("`-i` needs a value".to_string()));
+        assert_eq!(
+            parse("run --cell-width 12 a.fuck"),
+            Err("Unsupported cell width `12`".to_string())
+        );
+        assert_eq!(
+            parse("run --cell-width 16 --tape array a.fuck"),
+            Err("Wider cells are not supported, the array tape always has 8-bit cells".to_string())
+        );
+        assert_eq!(
+            parse("compile --cell-width 16 a.fuck"),
+            Err("`--cell-width` is not an option of `compile`".to_string())
+        );
+        assert_eq!(
+            parse("run --tape array --tape-size 10 a.fuck"),
+            Err(
+                "A tape size of `10` is not supported, the array tape always has 65536 cells"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            parse("run --tape-size 0 a.fuck"),
+            Err("Invalid tape size `0`".to_string())
+        );
+        assert_eq!(
+            parse("debug --window 100 --tape-size 100 a.fuck"),
+            Err("A window of `100` cells does not fit on a tape with 100 cells".to_string())
+        );
+        assert_eq!(
+            parse("debug --center 70000 a.fuck"),
+            Err("The center `70000` is outside of the tape with 65536 cells".to_string())
+        );
+        assert_eq!(
+            parse("repl --tape heap"),
+            Err("`--tape` is not an option of `repl`".to_string())
+        );
+        assert_eq!(
+            parse("repl a.fuck"),
+            Err("`repl` does not take a program".to_string())
+        );
+    }
+}
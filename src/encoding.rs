@@ -0,0 +1,393 @@
+use std::error::Error;
+use std::fmt;
+
+use vm::Node;
+
+/// Every encoded program starts with these bytes, followed by the format version
+const MAGIC: &[u8] = b"RFBC";
+const VERSION: u8 = 1;
+
+/// How deeply loops can be nested in a program that is decoded, so that decoding cannot overflow the stack
+const MAX_NESTING: usize = 1000;
+
+/// Set in the tag of nodes that move the data pointer
+const MOVE_POINTER: u8 = 0x80;
+
+const SHIFT: u8 = 0;
+const INC: u8 = 1;
+const DEC: u8 = 2;
+const MUL: u8 = 3;
+const PRODUCT: u8 = 4;
+const ASSIGN: u8 = 5;
+const SCAN: u8 = 6;
+const OUT: u8 = 7;
+const IN: u8 = 8;
+const CONDITIONAL: u8 = 9;
+const LOOP: u8 = 10;
+const COMMENT: u8 = 11;
+
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum DecodeError {
+    InvalidHeader,
+    UnsupportedVersion(u8),
+    UnexpectedEnd,
+    InvalidTag(u8),
+    InvalidValue,
+    TooDeeplyNested,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            DecodeError::InvalidHeader => f.write_str("this is not a compiled program"),
+            DecodeError::UnsupportedVersion(v) => {
+                write!(f, "version {} of compiled programs is not supported", v)
+            }
+            DecodeError::UnexpectedEnd => f.write_str("the compiled program ends unexpectedly"),
+            DecodeError::InvalidTag(tag) => {
+                write!(f, "the compiled program has an invalid node tag {:#x}", tag)
+            }
+            DecodeError::InvalidValue => f.write_str("the compiled program has an invalid value"),
+            DecodeError::TooDeeplyNested => write!(
+                f,
+                "the compiled program nests more than {} loops",
+                MAX_NESTING
+            ),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+/// Encodes optimized code in a compact binary format that `decode` loads without parsing and optimizing
+/// the source again
+///
+/// Every node is a tag byte followed by its values. Signed numbers are zigzag encoded variable length
+/// integers, loop bodies are prefixed with their number of nodes.
+pub fn encode(code: &[Node]) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(VERSION);
+    encode_block(&mut bytes, code);
+    bytes
+}
+
+fn encode_block(bytes: &mut Vec<u8>, code: &[Node]) {
+    write_unsigned(bytes, code.len() as u64);
+
+    for node in code {
+        let tag = |kind: u8, move_pointer: bool| {
+            if move_pointer {
+                kind | MOVE_POINTER
+            } else {
+                kind
+            }
+        };
+
+        match *node {
+            Node::Shift(v) => {
+                bytes.push(SHIFT);
+                write_signed(bytes, v as i64);
+            }
+            Node::Inc(v, offset, move_pointer)
+            | Node::Dec(v, offset, move_pointer)
+            | Node::Assign(v, offset, move_pointer) => {
+                let kind = match *node {
+                    Node::Inc(_, _, _) => INC,
+                    Node::Dec(_, _, _) => DEC,
+                    _ => ASSIGN,
+                };
+                bytes.push(tag(kind, move_pointer));
+                bytes.push(v);
+                write_signed(bytes, offset as i64);
+            }
+            Node::Mul(v, into, offset, move_pointer) => {
+                bytes.push(tag(MUL, move_pointer));
+                write_signed(bytes, i64::from(v));
+                write_signed(bytes, into as i64);
+                write_signed(bytes, offset as i64);
+            }
+            Node::Product(v, into, first, second) => {
+                bytes.push(PRODUCT);
+                write_signed(bytes, i64::from(v));
+                write_signed(bytes, into as i64);
+                write_signed(bytes, first as i64);
+                write_signed(bytes, second as i64);
+            }
+            Node::Scan(v) => {
+                bytes.push(SCAN);
+                write_signed(bytes, v as i64);
+            }
+            Node::Out(offset, move_pointer) | Node::In(offset, move_pointer) => {
+                let kind = match *node {
+                    Node::Out(_, _) => OUT,
+                    _ => IN,
+                };
+                bytes.push(tag(kind, move_pointer));
+                write_signed(bytes, offset as i64);
+            }
+            Node::Conditional(ref body) => {
+                bytes.push(CONDITIONAL);
+                encode_block(bytes, body);
+            }
+            Node::Loop(ref l) => {
+                bytes.push(LOOP);
+                write_signed(bytes, l.offset as i64);
+                write_signed(bytes, l.stride as i64);
+                encode_block(bytes, &l.body);
+            }
+            Node::Comment(c) => {
+                bytes.push(COMMENT);
+                write_unsigned(bytes, u64::from(u32::from(c)));
+            }
+        }
+    }
+}
+
+fn write_unsigned(bytes: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        bytes.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    bytes.push(v as u8);
+}
+
+fn write_signed(bytes: &mut Vec<u8>, v: i64) {
+    write_unsigned(bytes, ((v << 1) ^ (v >> 63)) as u64);
+}
+
+/// Whether `bytes` start like code encoded with `encode` in a supported version, rather than like source code
+pub fn is_encoded(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC) && bytes.get(MAGIC.len()) == Some(&VERSION)
+}
+
+/// Loads code encoded with `encode`
+pub fn decode(bytes: &[u8]) -> Result<Vec<Node>, DecodeError> {
+    if !bytes.starts_with(MAGIC) {
+        return Err(DecodeError::InvalidHeader);
+    }
+
+    let mut reader = Reader {
+        bytes,
+        position: MAGIC.len(),
+        depth: 0,
+    };
+    let version = reader.byte()?;
+    if version != VERSION {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+
+    let code = reader.block()?;
+    if reader.position != bytes.len() {
+        return Err(DecodeError::InvalidValue);
+    }
+    Ok(code)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    /// The number of blocks that are being read
+    depth: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Result<u8, DecodeError> {
+        let byte = *self
+            .bytes
+            .get(self.position)
+            .ok_or(DecodeError::UnexpectedEnd)?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn unsigned(&mut self) -> Result<u64, DecodeError> {
+        let mut v = 0;
+
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            let bits = u64::from(byte & 0x7f);
+            // The last byte only has room for the highest bit
+            if bits << shift >> shift != bits {
+                return Err(DecodeError::InvalidValue);
+            }
+            v |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+        Err(DecodeError::InvalidValue)
+    }
+
+    fn signed(&mut self) -> Result<i64, DecodeError> {
+        let v = self.unsigned()?;
+        Ok(((v >> 1) as i64) ^ -((v & 1) as i64))
+    }
+
+    fn offset(&mut self) -> Result<isize, DecodeError> {
+        let v = self.signed()?;
+        if v < isize::MIN as i64 || v > isize::MAX as i64 {
+            return Err(DecodeError::InvalidValue);
+        }
+        Ok(v as isize)
+    }
+
+    fn factor(&mut self) -> Result<i16, DecodeError> {
+        let v = self.signed()?;
+        if v < i64::from(i16::MIN) || v > i64::from(i16::MAX) {
+            return Err(DecodeError::InvalidValue);
+        }
+        Ok(v as i16)
+    }
+
+    fn block(&mut self) -> Result<Vec<Node>, DecodeError> {
+        let length = self.unsigned()?;
+        // Every node takes at least one byte, so this does not allocate more than the input size
+        if length > (self.bytes.len() - self.position) as u64 {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+
+        if self.depth > MAX_NESTING {
+            return Err(DecodeError::TooDeeplyNested);
+        }
+
+        self.depth += 1;
+        let mut code = Vec::with_capacity(length as usize);
+        for _ in 0..length {
+            code.push(self.node()?);
+        }
+        self.depth -= 1;
+        Ok(code)
+    }
+
+    fn node(&mut self) -> Result<Node, DecodeError> {
+        let tag = self.byte()?;
+        let move_pointer = tag & MOVE_POINTER != 0;
+        let kind = tag & !MOVE_POINTER;
+
+        if move_pointer && !matches!(kind, INC | DEC | MUL | ASSIGN | OUT | IN) {
+            return Err(DecodeError::InvalidTag(tag));
+        }
+
+        let node = match kind {
+            SHIFT => Node::Shift(self.offset()?),
+            INC => Node::Inc(self.byte()?, self.offset()?, move_pointer),
+            DEC => Node::Dec(self.byte()?, self.offset()?, move_pointer),
+            ASSIGN => Node::Assign(self.byte()?, self.offset()?, move_pointer),
+            MUL => Node::Mul(self.factor()?, self.offset()?, self.offset()?, move_pointer),
+            PRODUCT => Node::Product(
+                self.factor()?,
+                self.offset()?,
+                self.offset()?,
+                self.offset()?,
+            ),
+            SCAN => Node::Scan(self.offset()?),
+            OUT => Node::Out(self.offset()?, move_pointer),
+            IN => Node::In(self.offset()?, move_pointer),
+            CONDITIONAL => Node::Conditional(self.block()?),
+            LOOP => Node::new_loop(self.offset()?, self.offset()?, self.block()?),
+            COMMENT => {
+                let c = self.unsigned()?;
+                let c = ::std::char::from_u32(c as u32)
+                    .filter(|_| c <= u64::from(u32::MAX))
+                    .ok_or(DecodeError::InvalidValue)?;
+                Node::Comment(c)
+            }
+            _ => return Err(DecodeError::InvalidTag(tag)),
+        };
+
+        Ok(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use optimizer::{optimize_code, OptimizationOptions};
+    use parser::parse_code;
+
+    #[test]
+    fn it_should_encode_and_decode_every_node() {
+        let code = vec![
+            Node::Shift(-300),
+            Node::Inc(255, 1, true),
+            Node::Dec(3, -2, false),
+            Node::Mul(-256, 3, -1, true),
+            Node::Product(2, 1, 2, 3),
+            Node::Assign(0, 70_000, false),
+            Node::Scan(-2),
+            Node::Out(4, true),
+            Node::In(0, false),
+            Node::Conditional(vec![Node::new_loop(2, -1, vec![Node::Shift(-1)])]),
+            Node::Comment('ü'),
+        ];
+
+        assert_eq!(decode(&encode(&code)), Ok(code));
+    }
+
+    #[test]
+    fn it_should_encode_optimized_code_compactly() {
+        let parsed = parse_code(&mut "++++[->++<]>[>]<,.[-]".as_bytes()).unwrap();
+        let optimized = optimize_code(&parsed, &OptimizationOptions::default());
+        let encoded = encode(&optimized);
+
+        assert_eq!(&encoded[..5], b"RFBC\x01");
+        assert!(encoded.len() < 32);
+        assert_eq!(decode(&encoded), Ok(optimized));
+    }
+
+    #[test]
+    fn it_should_reject_invalid_input() {
+        let encoded = encode(&[Node::Shift(1), Node::Scan(2)]);
+
+        assert_eq!(decode(b"brainfuck"), Err(DecodeError::InvalidHeader));
+        assert_eq!(decode(b"RFBC\x02"), Err(DecodeError::UnsupportedVersion(2)));
+        assert_eq!(
+            decode(&encoded[..encoded.len() - 1]),
+            Err(DecodeError::UnexpectedEnd)
+        );
+        assert_eq!(
+            decode(b"RFBC\x01\x01\x0f"),
+            Err(DecodeError::InvalidTag(15))
+        );
+        assert_eq!(
+            decode(b"RFBC\x01\x01\x86\x02"),
+            Err(DecodeError::InvalidTag(0x86))
+        );
+        assert_eq!(
+            decode(b"RFBC\x01\x01\x03\x80\x80\x04\x00\x00"),
+            Err(DecodeError::InvalidValue)
+        );
+        assert_eq!(
+            decode(b"RFBC\x01\x01\x00\xff\xff\xff\xff\xff\xff\xff\xff\xff\x02"),
+            Err(DecodeError::InvalidValue)
+        );
+    }
+
+    #[test]
+    fn it_should_tell_encoded_code_from_source_code() {
+        assert!(is_encoded(&encode(&[Node::Shift(1)])));
+        assert!(!is_encoded(b"RFBC is a nice name\n+++."));
+        assert!(!is_encoded(b"RFBC"));
+        assert!(!is_encoded(b"+++."));
+    }
+
+    #[test]
+    fn it_should_limit_the_nesting_of_loops() {
+        let nested = |depth| (0..depth).fold(vec![], |body, _| vec![Node::Conditional(body)]);
+        let mut too_deep = b"RFBC\x01".to_vec();
+        for _ in 0..20_000 {
+            too_deep.extend_from_slice(b"\x01\x09");
+        }
+
+        assert_eq!(
+            decode(&encode(&nested(MAX_NESTING))),
+            Ok(nested(MAX_NESTING))
+        );
+        assert_eq!(
+            decode(&encode(&nested(MAX_NESTING + 1))),
+            Err(DecodeError::TooDeeplyNested)
+        );
+        assert_eq!(decode(&too_deep), Err(DecodeError::TooDeeplyNested));
+    }
+}
@@ -0,0 +1,523 @@
+use optimizer::{FilterComments, OptimizationStep};
+use vm::Node;
+
+/// How `format_code` lays out brainfuck source
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FormatOptions {
+    /// The number of spaces loop bodies are indented by per level of nesting
+    pub indent: usize,
+    /// The column lines are wrapped at, lines are never wrapped if `None`
+    pub width: Option<usize>,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            indent: 4,
+            width: None,
+        }
+    }
+}
+
+/// What the current line ends with
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Last {
+    Nothing,
+    /// An operator of the given kind, see `kind`
+    Operator(u8),
+    /// A word of a comment that is not finished yet
+    Word,
+    /// Whitespace after a word of a comment
+    Space,
+    /// A `[` or `]` on a line of its own, only comments from the same source line follow it
+    Bracket,
+}
+
+struct Layout<'a> {
+    options: &'a FormatOptions,
+    lines: Vec<String>,
+    line: String,
+    /// The nesting depth of the current line
+    line_depth: usize,
+    depth: usize,
+    last: Last,
+    /// Where the last word of a comment starts in the current line
+    word_start: usize,
+    /// The whitespace between two words of a comment, kept to keep comments aligned
+    spaces: String,
+    has_comment: bool,
+    /// The line breaks in the source since the last character that was not whitespace
+    newlines: usize,
+}
+
+/// The groups operators are collected in: `+-`, `<>` and `.,`
+fn kind(c: char) -> u8 {
+    match c {
+        '+' | '-' => 0,
+        '<' | '>' => 1,
+        _ => 2,
+    }
+}
+
+/// The source of a node that is not a loop, `None` for nodes that only the optimizer creates
+fn operators(node: &Node) -> Option<String> {
+    let text = match *node {
+        Node::Shift(v) if v > 0 => ">".repeat(v as usize),
+        Node::Shift(v) => "<".repeat(v.unsigned_abs()),
+        Node::Inc(v, 0, false) => "+".repeat(v as usize),
+        Node::Dec(v, 0, false) => "-".repeat(v as usize),
+        Node::Out(0, false) => ".".to_string(),
+        Node::In(0, false) => ",".to_string(),
+        Node::Comment(c) => c.to_string(),
+        _ => return None,
+    };
+    Some(text)
+}
+
+impl<'a> Layout<'a> {
+    fn push(&mut self, c: char) {
+        if self.line.is_empty() {
+            self.line_depth = self.depth;
+        }
+        self.line.push(c);
+    }
+
+    fn end_line(&mut self) {
+        if !self.line.is_empty() {
+            let indent = " ".repeat(self.line_depth * self.options.indent);
+            self.lines.push(indent + self.line.trim_end());
+            self.line.clear();
+        }
+        self.last = Last::Nothing;
+        self.has_comment = false;
+    }
+
+    fn blank_line(&mut self) {
+        self.end_line();
+        if self.lines.last().is_some_and(|l| !l.is_empty()) {
+            self.lines.push(String::new());
+        }
+    }
+
+    /// Whether the current line is longer than the configured width
+    fn overflows(&self) -> bool {
+        let width = self.line_depth * self.options.indent + self.line.chars().count();
+        self.options.width.is_some_and(|w| width > w)
+    }
+
+    /// Starts a line for what follows after line breaks in the source, blank lines are kept as one
+    fn break_for(&mut self, new_line: bool) {
+        if self.newlines > 1 {
+            self.blank_line();
+        } else if new_line {
+            self.end_line();
+        }
+        self.newlines = 0;
+    }
+
+    fn operator(&mut self, c: char) {
+        let after_comment = self.has_comment && self.newlines > 0;
+        self.break_for(after_comment || self.last == Last::Bracket);
+
+        if self.last != Last::Operator(kind(c)) && !self.line.is_empty() {
+            self.push(' ');
+        }
+        self.push(c);
+        self.last = Last::Operator(kind(c));
+
+        if self.overflows() {
+            self.line.pop();
+            self.end_line();
+            self.push(c);
+            self.last = Last::Operator(kind(c));
+        }
+    }
+
+    fn comment(&mut self, c: char) {
+        if c == '\n' {
+            self.newlines += 1;
+        }
+        if c.is_whitespace() {
+            if self.last == Last::Word {
+                self.last = Last::Space;
+                self.spaces.clear();
+            }
+            if self.last == Last::Space && c != '\n' && c != '\r' {
+                self.spaces.push(c);
+            }
+            return;
+        }
+
+        let own_line = self.newlines > 0 && !self.line.is_empty();
+        self.break_for(own_line);
+
+        if self.last == Last::Space {
+            let spaces = self.spaces.clone();
+            self.line.push_str(&spaces);
+        } else if self.last != Last::Word && !self.line.is_empty() {
+            self.push(' ');
+        }
+        if self.last != Last::Word {
+            self.word_start = self.line.len();
+        }
+        self.push(c);
+        self.last = Last::Word;
+        self.has_comment = true;
+
+        // Words are moved to the next line as a whole
+        if self.overflows() && self.word_start > 0 {
+            let word = self.line.split_off(self.word_start);
+            self.end_line();
+            for c in word.chars() {
+                self.push(c);
+            }
+            self.word_start = 0;
+            self.last = Last::Word;
+            self.has_comment = true;
+        }
+    }
+
+    /// A loop that only contains operators on one line, if it fits
+    fn compact(&self, body: &[Node]) -> Option<String> {
+        let mut text = "[".to_string();
+        for node in body {
+            match *node {
+                Node::Comment(c) if c.is_whitespace() => {}
+                Node::Comment(_) | Node::Conditional(_) => return None,
+                ref node => text.push_str(&operators(node)?),
+            }
+        }
+        text.push(']');
+
+        let width = self.depth * self.options.indent + text.chars().count();
+        if self.options.width.is_some_and(|w| width > w) {
+            None
+        } else {
+            Some(text)
+        }
+    }
+
+    fn bracket(&mut self, c: char) {
+        self.end_line();
+        self.push(c);
+        self.last = Last::Bracket;
+    }
+
+    fn block(&mut self, code: &[Node]) -> Option<()> {
+        for node in code {
+            match *node {
+                Node::Conditional(ref body) => {
+                    self.break_for(true);
+
+                    match self.compact(body) {
+                        Some(text) => {
+                            for c in text.chars() {
+                                self.push(c);
+                            }
+                            self.last = Last::Bracket;
+                        }
+                        None => {
+                            self.bracket('[');
+                            self.depth += 1;
+                            self.block(body)?;
+                            self.depth -= 1;
+                            self.newlines = 0;
+                            self.bracket(']');
+                        }
+                    }
+                }
+                Node::Comment(c) => self.comment(c),
+                ref node => {
+                    for c in operators(node)?.chars() {
+                        self.operator(c);
+                    }
+                }
+            }
+        }
+        Some(())
+    }
+}
+
+/// Writes characters of the parser, which are bytes of the source, as these bytes
+fn to_bytes(text: &str) -> Vec<u8> {
+    let mut bytes = vec![];
+    for c in text.chars() {
+        if (c as u32) < 256 {
+            bytes.push(c as u8);
+        } else {
+            bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+        }
+    }
+    bytes
+}
+
+/// Formats parsed code, as returned by `parser::parse_code`. Optimized code has no source to format, so
+/// it gives `None`.
+///
+/// Every loop that contains comments or other loops gets its own lines for `[` and `]` with the body
+/// indented between them, shorter loops go on one line. Operators are grouped into runs of `+-`, `<>` and
+/// `.,`, comments stay on the line they were on and blank lines are kept. Only whitespace and comments
+/// change, so the formatted code parses to the same instructions.
+///
+/// For example `+++[>++[-]<-]>. print it` becomes
+///
+/// ```text
+/// +++
+/// [
+///     > ++
+///     [-]
+///     < -
+/// ]
+/// > . print it
+/// ```
+pub fn format_code(code: &[Node], options: &FormatOptions) -> Option<Vec<u8>> {
+    let mut layout = Layout {
+        options,
+        lines: vec![],
+        line: String::new(),
+        line_depth: 0,
+        depth: 0,
+        last: Last::Nothing,
+        word_start: 0,
+        spaces: String::new(),
+        has_comment: false,
+        newlines: 0,
+    };
+
+    layout.block(code)?;
+    layout.end_line();
+
+    let mut text = layout.lines.join("\n");
+    if !text.is_empty() {
+        text.push('\n');
+    }
+    Some(to_bytes(&text))
+}
+
+/// Drops loops that never run and merges runs of operators, `all_zero` is whether every cell is zero
+/// when the block starts
+fn minify_block(code: &[Node], mut all_zero: bool) -> Vec<Node> {
+    let mut minified: Vec<Node> = vec![];
+
+    for node in code {
+        let merged = match (minified.last(), node) {
+            (Some(&Node::Inc(x, 0, false)), &Node::Inc(y, 0, false)) => Some(x.wrapping_add(y)),
+            (Some(&Node::Inc(x, 0, false)), &Node::Dec(y, 0, false)) => Some(x.wrapping_sub(y)),
+            _ => None,
+        };
+        if let Some(v) = merged {
+            minified.pop();
+            if v != 0 {
+                minified.push(Node::Inc(v, 0, false));
+            }
+            continue;
+        }
+
+        match *node {
+            Node::Shift(y) => match minified.last_mut() {
+                Some(Node::Shift(x)) => {
+                    *x += y;
+                    if *x == 0 {
+                        minified.pop();
+                    }
+                }
+                _ => minified.push(Node::Shift(y)),
+            },
+            Node::Dec(v, 0, false) => {
+                all_zero = false;
+                minified.push(Node::Inc(0u8.wrapping_sub(v), 0, false));
+            }
+            Node::Inc(_, 0, false) | Node::In(0, false) => {
+                all_zero = false;
+                minified.push(node.clone());
+            }
+            // A loop never runs on an empty tape or right after another loop, both leave the cell at zero
+            Node::Conditional(_)
+                if all_zero || matches!(minified.last(), Some(Node::Conditional(_))) => {}
+            Node::Conditional(ref body) => {
+                minified.push(Node::Conditional(minify_block(body, false)));
+            }
+            ref node => minified.push(node.clone()),
+        }
+    }
+    minified
+}
+
+fn print_minified(text: &mut String, code: &[Node]) -> Option<()> {
+    for node in code {
+        match *node {
+            // Cells wrap around, so `+` * 200 is the same as `-` * 56
+            Node::Inc(v, 0, false) if v > 128 => text.push_str(&"-".repeat(256 - v as usize)),
+            Node::Conditional(ref body) => {
+                text.push('[');
+                print_minified(text, body)?;
+                text.push(']');
+            }
+            ref node => text.push_str(&operators(node)?),
+        }
+    }
+    Some(())
+}
+
+/// The shortest source of parsed code without changing what it does when it starts on an empty tape
+///
+/// Comments are removed, as are loops that never run, like an initial comment loop. Operators that cancel
+/// each other out like `+-` or `><` are removed and runs of `+` and `-` are written in the shorter
+/// direction. Like `format_code` it gives `None` for optimized code.
+pub fn minify_code(code: &[Node]) -> Option<String> {
+    let mut text = String::new();
+    print_minified(&mut text, &minify_block(&FilterComments.apply(code), true))?;
+    Some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::parse_code;
+    use vm::State;
+
+    fn format_with(code: &[u8], options: &FormatOptions) -> String {
+        let parsed = parse_code(&mut &code[..]).unwrap();
+        let formatted = format_code(&parsed, options).unwrap();
+        let reparsed = parse_code(&mut formatted.as_slice()).unwrap();
+
+        assert_eq!(
+            FilterComments.apply(&reparsed),
+            FilterComments.apply(&parsed)
+        );
+        String::from_utf8(formatted).unwrap()
+    }
+
+    fn format(code: &str) -> String {
+        format_with(code.as_bytes(), &FormatOptions::default())
+    }
+
+    #[test]
+    fn it_should_indent_loops_by_their_depth() {
+        assert_eq!(
+            format("+++[>++[-]<-]>. print it"),
+            "+++\n[\n    > ++\n    [-]\n    < -\n]\n> . print it\n"
+        );
+        assert_eq!(
+            format("+[>[>[-]<-]<-]"),
+            "+\n[\n    >\n    [\n        >\n        [-]\n        < -\n    ]\n    < -\n]\n"
+        );
+    }
+
+    #[test]
+    fn it_should_group_operators() {
+        assert_eq!(
+            format("+ + +>>\n\n--<.,[\n-\n]"),
+            "+++ >>\n\n-- < .,\n[-]\n"
+        );
+    }
+
+    #[test]
+    fn it_should_keep_comments_next_to_their_code() {
+        assert_eq!(
+            format("set up   the\ncounter\n++++++++ eight\n[>++++ times four\n<-]\n>."),
+            "set up   the\n\
+             counter\n\
+             ++++++++ eight\n\
+             [\n    > ++++ times four\n    < -\n]\n\
+             > .\n"
+        );
+    }
+
+    #[test]
+    fn it_should_wrap_long_lines() {
+        let options = FormatOptions {
+            indent: 2,
+            width: Some(10),
+        };
+
+        assert_eq!(
+            format_with(b"++++++++++++>>[->+>+>+<<<] a long comment", &options),
+            "++++++++++\n++ >>\n[\n  - > + >\n  + > + <<\n  <\n] a long\ncomment\n"
+        );
+    }
+
+    #[test]
+    fn it_should_keep_bytes_of_comments() {
+        let parsed = parse_code(&mut "+ gr\u{fc}n".as_bytes()).unwrap();
+
+        assert_eq!(
+            format_code(&parsed, &FormatOptions::default()),
+            Some("+ gr\u{fc}n\n".as_bytes().to_vec())
+        );
+    }
+
+    #[test]
+    fn it_should_format_the_examples() {
+        let examples: [&[u8]; 3] = [
+            include_bytes!("../fuck/hello.fuck"),
+            include_bytes!("../fuck/hanoi.fuck"),
+            include_bytes!("../fuck/mandelbrot.fuck"),
+        ];
+
+        for example in examples.iter() {
+            let options = FormatOptions {
+                width: Some(80),
+                ..FormatOptions::default()
+            };
+            let formatted = format_with(example, &options);
+
+            assert_eq!(format_with(formatted.as_bytes(), &options), formatted);
+            assert!(formatted.lines().all(|l| l.chars().count() <= 80));
+        }
+    }
+
+    fn minify(code: &str) -> String {
+        minify_code(&parse_code(&mut code.as_bytes()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn it_should_minify_code() {
+        assert_eq!(
+            minify("[a comment, with. code] +++-- >< >>< ,.\n[-] [->+<] +"),
+            "+>,.[-]+"
+        );
+        assert_eq!(minify(">[-]<[x]<++>[-]"), "<++>[-]");
+        assert_eq!(minify("+[[-]>[<+>-]]"), "+[[-]>[<+>-]]");
+        assert_eq!(minify(&"+".repeat(200)), "-".repeat(56));
+        assert_eq!(minify("+>-<<>>-"), "+>--");
+    }
+
+    #[test]
+    fn it_should_not_format_optimized_code() {
+        let code = vec![
+            Node::Inc(1, 0, false),
+            Node::Conditional(vec![Node::Mul(2, 1, 0, false), Node::Assign(0, 0, false)]),
+        ];
+
+        assert_eq!(format_code(&code, &FormatOptions::default()), None);
+        assert_eq!(minify_code(&code), None);
+    }
+
+    #[test]
+    fn it_should_minify_the_examples_without_changing_their_output() {
+        let examples: [&[u8]; 2] = [
+            include_bytes!("../fuck/hello.fuck"),
+            include_bytes!("../fuck/hanoi.fuck"),
+        ];
+
+        for example in examples.iter() {
+            let minified = minify_code(&parse_code(&mut &example[..]).unwrap()).unwrap();
+            let run = |mut code: &[u8]| {
+                let mut stdout = vec![];
+                ::run_code(
+                    &mut code,
+                    &mut [].as_ref(),
+                    &mut stdout,
+                    &mut State::default(),
+                )
+                .unwrap();
+                stdout
+            };
+
+            assert!(minified.len() < example.len());
+            assert_eq!(run(minified.as_bytes()), run(example));
+        }
+    }
+}
@@ -0,0 +1,521 @@
+use std::fmt::Write;
+
+use vm::Node;
+
+/// An error in IR text, lines start at 1
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct IrError {
+    pub line: usize,
+    pub message: String,
+}
+
+/// Prints code as IR text, one node per line
+///
+/// For example `[Inc(3, 2, false), Mul(-1, 3, 0, false), Loop(1, 2, [Dec(1, 1, false), Shift(2)])]`
+/// becomes
+///
+/// ```text
+/// inc 3 @+2
+/// mul -1 from @0 into @+3
+/// loop @+1 stride +2 {
+///     dec 1 @+1
+///     shift +2
+/// }
+/// ```
+pub fn print_ir(code: &[Node]) -> String {
+    let mut text = String::new();
+    print_block(&mut text, code, 0);
+    text
+}
+
+/// Prints a single node on one line, loops are printed without their body
+pub fn describe(node: &Node) -> String {
+    match *node {
+        Node::Conditional(_) => "loop { ... }".to_string(),
+        Node::Loop(ref l) => format!(
+            "loop @{} stride {} {{ ... }}",
+            signed(l.offset),
+            signed(l.stride)
+        ),
+        _ => {
+            let mut text = String::new();
+            print_block(&mut text, ::std::slice::from_ref(node), 0);
+            text.trim_end().to_string()
+        }
+    }
+}
+
+/// Formats offsets and strides with an explicit sign, except for zero
+fn signed(v: isize) -> String {
+    if v == 0 {
+        "0".to_string()
+    } else {
+        format!("{:+}", v)
+    }
+}
+
+fn print_block(text: &mut String, code: &[Node], depth: usize) {
+    for node in code {
+        let indent = "    ".repeat(depth);
+        let moved = |move_pointer: bool| if move_pointer { " move" } else { "" };
+
+        let _ = match *node {
+            Node::Shift(v) => writeln!(text, "{}shift {}", indent, signed(v)),
+            Node::Inc(v, offset, move_pointer) => writeln!(
+                text,
+                "{}inc {} @{}{}",
+                indent,
+                v,
+                signed(offset),
+                moved(move_pointer)
+            ),
+            Node::Dec(v, offset, move_pointer) => writeln!(
+                text,
+                "{}dec {} @{}{}",
+                indent,
+                v,
+                signed(offset),
+                moved(move_pointer)
+            ),
+            Node::Mul(v, into, offset, move_pointer) => writeln!(
+                text,
+                "{}mul {} from @{} into @{}{}",
+                indent,
+                v,
+                signed(offset),
+                signed(offset + into),
+                moved(move_pointer)
+            ),
+            Node::Product(v, into, first, second) => writeln!(
+                text,
+                "{}product {} of @{} @{} into @{}",
+                indent,
+                v,
+                signed(first),
+                signed(second),
+                signed(into)
+            ),
+            Node::Assign(v, offset, move_pointer) => writeln!(
+                text,
+                "{}assign {} @{}{}",
+                indent,
+                v,
+                signed(offset),
+                moved(move_pointer)
+            ),
+            Node::Scan(v) => writeln!(text, "{}scan {}", indent, signed(v)),
+            Node::Out(offset, move_pointer) => writeln!(
+                text,
+                "{}out @{}{}",
+                indent,
+                signed(offset),
+                moved(move_pointer)
+            ),
+            Node::In(offset, move_pointer) => writeln!(
+                text,
+                "{}in @{}{}",
+                indent,
+                signed(offset),
+                moved(move_pointer)
+            ),
+            Node::Comment(c) => writeln!(text, "{}comment {:?}", indent, c),
+            Node::Conditional(ref body) => {
+                let _ = writeln!(text, "{}loop {{", indent);
+                print_block(text, body, depth + 1);
+                writeln!(text, "{}}}", indent)
+            }
+            Node::Loop(ref l) => {
+                let _ = writeln!(
+                    text,
+                    "{}loop @{} stride {} {{",
+                    indent,
+                    signed(l.offset),
+                    signed(l.stride)
+                );
+                print_block(text, &l.body, depth + 1);
+                writeln!(text, "{}}}", indent)
+            }
+        };
+    }
+}
+
+struct Token {
+    line: usize,
+    text: String,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, IrError> {
+    let mut tokens = vec![];
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            '\n' => {
+                line += 1;
+                chars.next();
+            }
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '#' => {
+                while chars.peek().is_some_and(|c| *c != '\n') {
+                    chars.next();
+                }
+            }
+            '{' | '}' => {
+                tokens.push(Token {
+                    line,
+                    text: c.to_string(),
+                });
+                chars.next();
+            }
+            '\'' => {
+                let mut literal = String::new();
+                literal.push(c);
+                chars.next();
+
+                loop {
+                    match chars.next() {
+                        Some('\\') => {
+                            literal.push('\\');
+                            if let Some(escaped) = chars.next() {
+                                literal.push(escaped);
+                            }
+                        }
+                        Some('\'') => {
+                            literal.push('\'');
+                            break;
+                        }
+                        Some(c) => literal.push(c),
+                        None => {
+                            return Err(IrError {
+                                line,
+                                message: "Unterminated character literal".to_string(),
+                            })
+                        }
+                    }
+                }
+                tokens.push(Token {
+                    line,
+                    text: literal,
+                });
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '{' || c == '}' || c == '#' {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token { line, text: word });
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn error(&self, message: String) -> IrError {
+        let line = self
+            .tokens
+            .get(self.position)
+            .or_else(|| self.tokens.last())
+            .map(|t| t.line)
+            .unwrap_or(1);
+
+        IrError { line, message }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(|t| t.text.as_str())
+    }
+
+    fn next(&mut self) -> Result<String, IrError> {
+        match self.tokens.get(self.position) {
+            Some(token) => {
+                self.position += 1;
+                Ok(token.text.clone())
+            }
+            None => Err(self.error("Unexpected end of input".to_string())),
+        }
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), IrError> {
+        let token = self.next()?;
+        if token == expected {
+            Ok(())
+        } else {
+            self.position -= 1;
+            Err(self.error(format!("Expected `{}`, found `{}`", expected, token)))
+        }
+    }
+
+    fn number<T: ::std::str::FromStr>(&mut self) -> Result<T, IrError> {
+        let token = self.next()?;
+        let digits = token.trim_start_matches('+');
+
+        digits.parse().map_err(|_| {
+            self.position -= 1;
+            self.error(format!("Invalid number `{}`", token))
+        })
+    }
+
+    fn offset(&mut self) -> Result<isize, IrError> {
+        let token = self.next()?;
+
+        match token
+            .strip_prefix('@')
+            .map(|o| o.trim_start_matches('+').parse())
+        {
+            Some(Ok(offset)) => Ok(offset),
+            _ => {
+                self.position -= 1;
+                Err(self.error(format!("Invalid offset `{}`", token)))
+            }
+        }
+    }
+
+    fn move_pointer(&mut self) -> bool {
+        if self.peek() == Some("move") {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn character(&mut self) -> Result<char, IrError> {
+        let token = self.next()?;
+        let inner = token
+            .strip_prefix('\'')
+            .and_then(|t| t.strip_suffix('\''))
+            .filter(|t| !t.is_empty());
+        let mut chars = inner.unwrap_or("").chars();
+
+        let c = match (chars.next(), chars.next()) {
+            (Some('\\'), Some(escaped)) => match escaped {
+                'n' => Some('\n'),
+                'r' => Some('\r'),
+                't' => Some('\t'),
+                '0' => Some('\0'),
+                '\\' | '\'' | '"' => Some(escaped),
+                'u' => {
+                    let hex: String = chars.by_ref().collect();
+                    hex.strip_prefix('{')
+                        .and_then(|h| h.strip_suffix('}'))
+                        .and_then(|h| u32::from_str_radix(h, 16).ok())
+                        .and_then(::std::char::from_u32)
+                }
+                _ => None,
+            },
+            (Some(c), None) => Some(c),
+            _ => None,
+        };
+
+        match c {
+            Some(c) if chars.next().is_none() => Ok(c),
+            _ => {
+                self.position -= 1;
+                Err(self.error(format!("Invalid character literal `{}`", token)))
+            }
+        }
+    }
+
+    fn block(&mut self, nested: bool) -> Result<Vec<Node>, IrError> {
+        let mut code = vec![];
+
+        loop {
+            match self.peek() {
+                Some("}") if nested => {
+                    self.position += 1;
+                    return Ok(code);
+                }
+                None if !nested => return Ok(code),
+                _ => code.push(self.node()?),
+            }
+        }
+    }
+
+    fn node(&mut self) -> Result<Node, IrError> {
+        let instruction = self.next()?;
+
+        let node = match instruction.as_str() {
+            "shift" => Node::Shift(self.number()?),
+            "inc" => Node::Inc(self.number()?, self.offset()?, self.move_pointer()),
+            "dec" => Node::Dec(self.number()?, self.offset()?, self.move_pointer()),
+            "assign" => Node::Assign(self.number()?, self.offset()?, self.move_pointer()),
+            "mul" => {
+                let value = self.number()?;
+                self.expect("from")?;
+                let offset = self.offset()?;
+                self.expect("into")?;
+                let into = self.offset()?;
+                Node::Mul(value, into - offset, offset, self.move_pointer())
+            }
+            "product" => {
+                let value = self.number()?;
+                self.expect("of")?;
+                let first = self.offset()?;
+                let second = self.offset()?;
+                self.expect("into")?;
+                Node::Product(value, self.offset()?, first, second)
+            }
+            "scan" => Node::Scan(self.number()?),
+            "out" => Node::Out(self.offset()?, self.move_pointer()),
+            "in" => Node::In(self.offset()?, self.move_pointer()),
+            "comment" => Node::Comment(self.character()?),
+            "loop" => {
+                if self.peek() == Some("{") {
+                    self.position += 1;
+                    Node::Conditional(self.block(true)?)
+                } else {
+                    let offset = self.offset()?;
+                    self.expect("stride")?;
+                    let stride = self.number()?;
+                    self.expect("{")?;
+                    Node::new_loop(offset, stride, self.block(true)?)
+                }
+            }
+            _ => {
+                self.position -= 1;
+                return Err(self.error(format!("Unknown instruction `{}`", instruction)));
+            }
+        };
+
+        Ok(node)
+    }
+}
+
+/// Parses IR text as printed by `print_ir`, `#` starts a comment that runs to the end of the line
+pub fn parse_ir(text: &str) -> Result<Vec<Node>, IrError> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        position: 0,
+    };
+
+    parser.block(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use optimizer::{optimize_code, OptimizationOptions};
+    use parser::parse_code;
+
+    #[test]
+    fn it_should_print_nodes() {
+        let code = vec![
+            Node::Inc(3, 2, false),
+            Node::Mul(-1, 3, 0, false),
+            Node::new_loop(1, 2, vec![Node::Dec(1, 1, false), Node::Shift(2)]),
+            Node::Conditional(vec![Node::Out(-1, true), Node::Comment('\n')]),
+            Node::Scan(-2),
+        ];
+
+        assert_eq!(
+            print_ir(&code),
+            "inc 3 @+2\n\
+             mul -1 from @0 into @+3\n\
+             loop @+1 stride +2 {\n    \
+                 dec 1 @+1\n    \
+                 shift +2\n\
+             }\n\
+             loop {\n    \
+                 out @-1 move\n    \
+                 comment '\\n'\n\
+             }\n\
+             scan -2\n"
+        );
+    }
+
+    #[test]
+    fn it_should_describe_single_nodes() {
+        assert_eq!(describe(&Node::Out(-1, true)), "out @-1 move");
+        assert_eq!(
+            describe(&Node::new_loop(1, 2, vec![Node::Dec(1, 1, false)])),
+            "loop @+1 stride +2 { ... }"
+        );
+    }
+
+    #[test]
+    fn it_should_parse_ir() {
+        let text = "
+            # Multiply the current cell into the next one
+            inc 2 @0
+            loop {
+                dec 1 @0 move
+                product -3 of @+1 @+2 into @-1
+                assign 7 @+1
+            }
+            in @+1 move
+            comment ' '
+        ";
+
+        assert_eq!(
+            parse_ir(text),
+            Ok(vec![
+                Node::Inc(2, 0, false),
+                Node::Conditional(vec![
+                    Node::Dec(1, 0, true),
+                    Node::Product(-3, -1, 1, 2),
+                    Node::Assign(7, 1, false),
+                ]),
+                Node::In(1, true),
+                Node::Comment(' '),
+            ])
+        );
+    }
+
+    #[test]
+    fn it_should_print_and_parse_optimized_code() {
+        for source in ["+++[->++<]>[>]<,.[-]", "hello\n[->+>+<<]'\\"].iter() {
+            let parsed = parse_code(&mut source.as_bytes()).unwrap();
+            let optimized = optimize_code(&parsed, &OptimizationOptions::default());
+
+            assert_eq!(parse_ir(&print_ir(&parsed)), Ok(parsed));
+            assert_eq!(parse_ir(&print_ir(&optimized)), Ok(optimized));
+        }
+    }
+
+    #[test]
+    fn it_should_return_errors_with_lines() {
+        assert_eq!(
+            parse_ir("inc 1 @0\nloop {\n  jump 3\n}"),
+            Err(IrError {
+                line: 3,
+                message: "Unknown instruction `jump`".to_string()
+            })
+        );
+        assert_eq!(
+            parse_ir("mul 2 from @0 to @+1"),
+            Err(IrError {
+                line: 1,
+                message: "Expected `into`, found `to`".to_string()
+            })
+        );
+        assert_eq!(
+            parse_ir("loop {\nout @0\n"),
+            Err(IrError {
+                line: 2,
+                message: "Unexpected end of input".to_string()
+            })
+        );
+        assert_eq!(
+            parse_ir("inc 300 @0"),
+            Err(IrError {
+                line: 1,
+                message: "Invalid number `300`".to_string()
+            })
+        );
+    }
+}
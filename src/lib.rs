@@ -0,0 +1,442 @@
+extern crate memchr;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+
+pub mod analyzer;
+pub mod encoding;
+pub mod format;
+pub mod ir;
+pub mod lint;
+pub mod optimizer;
+pub mod parser;
+pub mod tape;
+pub mod view;
+pub mod vm;
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+
+use optimizer::OptimizationOptions;
+use parser::ParserError;
+use tape::Tape;
+use vm::{Node, RuntimeError, State};
+
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ExecutionError {
+    Parse(ParserError),
+    Run(RuntimeError),
+}
+
+impl fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            ExecutionError::Parse(e) => e.fmt(f),
+            ExecutionError::Run(e) => e.fmt(f),
+        }
+    }
+}
+
+impl Error for ExecutionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ExecutionError::Parse(e) => Some(e),
+            ExecutionError::Run(e) => Some(e),
+        }
+    }
+}
+
+/// Run some brainfuck code
+pub fn run_code<F: BufRead, R: Read, W: Write, C: Tape>(
+    code: &mut F,
+    stdin: &mut R,
+    stdout: &mut W,
+    s: &mut State<C>,
+) -> Result<(), ExecutionError> {
+    run_code_with_options(code, &OptimizationOptions::default(), stdin, stdout, s)
+}
+
+/// Run some brainfuck code with a selection of optimizations
+pub fn run_code_with_options<F: BufRead, R: Read, W: Write, C: Tape>(
+    code: &mut F,
+    options: &OptimizationOptions,
+    stdin: &mut R,
+    stdout: &mut W,
+    s: &mut State<C>,
+) -> Result<(), ExecutionError> {
+    Program::compile(code, options)
+        .map_err(ExecutionError::Parse)?
+        .run(stdin, stdout, s)
+        .map_err(ExecutionError::Run)
+}
+
+/// Brainfuck code that is parsed and optimized once and can then run any number of times, also from
+/// multiple threads at once
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Program {
+    /// The unoptimized code, if the program was compiled from source
+    parsed: Option<Vec<Node>>,
+    optimized: Vec<Node>,
+}
+
+impl Program {
+    pub fn compile<F: BufRead>(
+        code: &mut F,
+        options: &OptimizationOptions,
+    ) -> Result<Program, ParserError> {
+        let parsed = parser::parse_code(code)?;
+        let optimized = optimizer::optimize_code(&parsed, options);
+
+        Ok(Program {
+            parsed: Some(parsed),
+            optimized,
+        })
+    }
+
+    /// A program from code that is already optimized, like code loaded with `encoding::decode`. Errors
+    /// of such programs have locations in the optimized code.
+    pub fn from_optimized(code: Vec<Node>) -> Program {
+        Program {
+            parsed: None,
+            optimized: code,
+        }
+    }
+
+    /// The optimized code
+    pub fn code(&self) -> &[Node] {
+        &self.optimized
+    }
+
+    /// Runs the program. Infinite loops are reported at their location in the source code, unless the
+    /// program was loaded optimized or has read more than `MAX_REPLAYED_INPUT` bytes of input before.
+    pub fn run<R: Read, W: Write, C: Tape>(
+        &self,
+        stdin: &mut R,
+        stdout: &mut W,
+        s: &mut State<C>,
+    ) -> Result<(), RuntimeError> {
+        let parsed = match self.parsed {
+            Some(ref parsed) => parsed,
+            None => return vm::run_block(stdin, stdout, &self.optimized, s),
+        };
+
+        let initial_state = s.clone();
+        let mut recorder = Recorder {
+            inner: stdin,
+            read: Some(vec![]),
+        };
+
+        match vm::run_block(&mut recorder, stdout, &self.optimized, s) {
+            Err(RuntimeError::InfiniteLoop(location)) => {
+                // The location refers to the optimized code, so run the unoptimized code again with the
+                // same input to find the loop in the source
+                let read = match recorder.read {
+                    Some(ref read) => read,
+                    None => return Err(RuntimeError::InfiniteLoop(location)),
+                };
+                let mut replayed_state = initial_state;
+                let replayed = vm::run_block(
+                    &mut read.as_slice(),
+                    &mut io::sink(),
+                    parsed,
+                    &mut replayed_state,
+                );
+
+                match replayed {
+                    Err(e @ RuntimeError::InfiniteLoop(_)) => Err(e),
+                    _ => Err(RuntimeError::InfiniteLoop(location)),
+                }
+            }
+            result => result,
+        }
+    }
+}
+
+/// The most input a program can read and still have its infinite loops located in the source code
+pub const MAX_REPLAYED_INPUT: usize = 1 << 20;
+
+/// Keeps a copy of everything read from the inner reader, until it gets longer than `MAX_REPLAYED_INPUT`
+struct Recorder<'a, R: Read + 'a> {
+    inner: &'a mut R,
+    read: Option<Vec<u8>>,
+}
+
+impl<'a, R: Read> Read for Recorder<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        let is_full = match self.read {
+            Some(ref mut recorded) => {
+                recorded.extend_from_slice(&buf[..read]);
+                recorded.len() > MAX_REPLAYED_INPUT
+            }
+            None => false,
+        };
+        if is_full {
+            self.read = None;
+        }
+        Ok(read)
+    }
+}
+
+/// Runs brainfuck code on a state that is kept from one run to the next
+pub struct Interpreter {
+    options: OptimizationOptions,
+    state: State,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::with_options(OptimizationOptions::default())
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Interpreter::default()
+    }
+
+    pub fn with_options(options: OptimizationOptions) -> Self {
+        Interpreter {
+            options,
+            state: State::default(),
+        }
+    }
+
+    /// Runs code reading from `stdin` and writing to `stdout`
+    pub fn run<F: BufRead, R: Read, W: Write>(
+        &mut self,
+        code: &mut F,
+        stdin: &mut R,
+        stdout: &mut W,
+    ) -> Result<(), ExecutionError> {
+        run_code_with_options(code, &self.options, stdin, stdout, &mut self.state)
+    }
+
+    /// Runs code with all of its input given at once and returns everything it writes
+    pub fn run_str(&mut self, code: &str, mut stdin: &[u8]) -> Result<Vec<u8>, ExecutionError> {
+        let mut stdout = vec![];
+        self.run(&mut code.as_bytes(), &mut stdin, &mut stdout)?;
+        Ok(stdout)
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn state_mut(&mut self) -> &mut State {
+        &mut self.state
+    }
+
+    /// Starts over with an empty tape, keeping the EOF policy
+    pub fn reset(&mut self) {
+        self.state = State {
+            eof: self.state.eof,
+            ..State::default()
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::Location;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Instant;
+    use tape::{HeapTape, SparseTape};
+
+    #[test]
+    fn it_should_return_parser_errors_when_running_code() {
+        let stdin = vec![];
+        let mut stdout = vec![];
+        let mut s = State::default();
+
+        let code = "[[]";
+        let result = run_code(
+            &mut code.as_bytes(),
+            &mut stdin.as_slice(),
+            &mut stdout,
+            &mut s,
+        );
+
+        assert_eq!(
+            result,
+            Err(ExecutionError::Parse(ParserError::MissingDelimiter(
+                Location { line: 1, column: 1 }
+            )))
+        );
+    }
+
+    #[test]
+    fn it_should_report_infinite_loops_at_their_source_location() {
+        let stdin = vec![3];
+        let mut stdout = vec![];
+        let mut s = State::default();
+
+        let code = "++[-]\n,[>+<]";
+        let result = run_code(
+            &mut code.as_bytes(),
+            &mut stdin.as_slice(),
+            &mut stdout,
+            &mut s,
+        );
+
+        assert_eq!(
+            result,
+            Err(ExecutionError::Run(RuntimeError::InfiniteLoop(Location {
+                line: 2,
+                column: 2
+            })))
+        );
+    }
+
+    #[test]
+    fn it_should_report_infinite_loops_in_the_optimized_code_after_too_much_input() {
+        let mut stdin = vec![1; MAX_REPLAYED_INPUT + 1];
+        stdin.push(0);
+        let program = Program::compile(
+            &mut "++[-]\n,[,]+[>+<]".as_bytes(),
+            &OptimizationOptions::default(),
+        )
+        .unwrap();
+        let optimized = Program::from_optimized(program.code().to_vec());
+
+        let result = program.run(&mut stdin.as_slice(), &mut vec![], &mut State::default());
+        let expected = optimized.run(&mut stdin.as_slice(), &mut vec![], &mut State::default());
+
+        assert!(matches!(result, Err(RuntimeError::InfiniteLoop(_))));
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn it_should_keep_the_state_between_runs() {
+        let mut interpreter = Interpreter::new();
+
+        assert_eq!(interpreter.run_str("+++>,", b"a"), Ok(vec![]));
+        assert_eq!(interpreter.state().pos, 1);
+        assert_eq!(interpreter.run_str("<.>.", b""), Ok(vec![3, b'a']));
+
+        interpreter.reset();
+        assert_eq!(interpreter.state().pos, 0);
+        assert_eq!(interpreter.run_str(".", b""), Ok(vec![0]));
+    }
+
+    #[test]
+    fn it_should_return_errors_from_the_interpreter() {
+        let mut interpreter = Interpreter::new();
+
+        assert_eq!(
+            interpreter.run_str(",", b""),
+            Err(ExecutionError::Run(RuntimeError::ReadError(
+                "No data from stdin".to_string()
+            )))
+        );
+    }
+
+    #[test]
+    fn it_should_format_execution_errors() {
+        let error = ExecutionError::Parse(ParserError::MissingDelimiter(Location {
+            line: 1,
+            column: 5,
+        }));
+
+        assert_eq!(format!("{}", error), "1:5: this `[` is never closed");
+        assert!(error.source().is_some());
+    }
+
+    #[test]
+    fn it_should_run_a_program_many_times() {
+        let program = Program::compile(
+            &mut ",[->++<]>.".as_bytes(),
+            &OptimizationOptions::default(),
+        )
+        .unwrap();
+
+        for i in 0..10u8 {
+            let mut stdout = vec![];
+            program
+                .run(&mut [i].as_ref(), &mut stdout, &mut State::default())
+                .unwrap();
+
+            assert_eq!(stdout, vec![i * 2]);
+        }
+    }
+
+    #[test]
+    fn it_should_run_a_program_from_multiple_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Program>();
+
+        let program = Arc::new(
+            Program::compile(&mut ",+.".as_bytes(), &OptimizationOptions::default()).unwrap(),
+        );
+        let threads: Vec<_> = (0..8u8)
+            .map(|i| {
+                let program = Arc::clone(&program);
+                thread::spawn(move || {
+                    let mut stdout = vec![];
+                    program
+                        .run(&mut [i].as_ref(), &mut stdout, &mut State::default())
+                        .unwrap();
+                    stdout
+                })
+            })
+            .collect();
+
+        for (i, thread) in threads.into_iter().enumerate() {
+            assert_eq!(thread.join().unwrap(), vec![i as u8 + 1]);
+        }
+    }
+
+    #[test]
+    fn it_should_run_programs_loaded_from_optimized_code() {
+        let compiled = Program::compile(
+            &mut "++[>+++<-]>.".as_bytes(),
+            &OptimizationOptions::default(),
+        )
+        .unwrap();
+        let loaded =
+            Program::from_optimized(encoding::decode(&encoding::encode(compiled.code())).unwrap());
+        let mut stdout = vec![];
+
+        loaded
+            .run(&mut [].as_ref(), &mut stdout, &mut State::default())
+            .unwrap();
+
+        assert_eq!(loaded.code(), compiled.code());
+        assert_eq!(stdout, vec![6]);
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_tapes() {
+        fn bench<C: Tape>(name: &str, program: &Program, runs: u32) {
+            let start = Instant::now();
+            for _ in 0..runs {
+                let mut s = State::<C>::with_len(vm::NUMBER_OF_CELLS).unwrap();
+                program
+                    .run(&mut [].as_ref(), &mut io::sink(), &mut s)
+                    .unwrap();
+            }
+            println!("{}: {:?}", name, start.elapsed() / runs);
+        }
+
+        let programs: [(&str, &[u8], u32); 2] = [
+            ("hanoi", include_bytes!("../fuck/hanoi.fuck"), 10),
+            ("mandelbrot", include_bytes!("../fuck/mandelbrot.fuck"), 1),
+        ];
+        for (name, code, runs) in programs.iter() {
+            let program =
+                Program::compile(&mut &code[..], &OptimizationOptions::default()).unwrap();
+
+            bench::<[u8; vm::NUMBER_OF_CELLS]>(&format!("{} array", name), &program, *runs);
+            bench::<HeapTape>(&format!("{} heap", name), &program, *runs);
+            bench::<SparseTape>(&format!("{} sparse", name), &program, *runs);
+        }
+    }
+}
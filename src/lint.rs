@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use analyzer::written_cells;
+use parser::Location;
+use vm::Node;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Severity {
+    Note,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Severity::Note => f.write_str("note"),
+            Severity::Warning => f.write_str("warning"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LintKind {
+    /// A loop that is never entered because the current cell is always zero
+    LoopNeverRuns,
+    /// A loop right after another loop, the current cell is zero after every loop
+    LoopAfterLoop,
+    /// `[-]` or `[+]` on a cell that is already zero
+    RedundantClear,
+    /// `+-`, `-+`, `<>` or `><`
+    CancellingPair,
+    /// Code after a loop that is entered but never terminates
+    UnreachableCode,
+}
+
+impl LintKind {
+    pub fn severity(self) -> Severity {
+        match self {
+            LintKind::CancellingPair | LintKind::RedundantClear => Severity::Note,
+            _ => Severity::Warning,
+        }
+    }
+
+    pub fn message(self) -> &'static str {
+        match self {
+            LintKind::LoopNeverRuns => {
+                "this loop never runs because the current cell is always zero"
+            }
+            LintKind::LoopAfterLoop => {
+                "this loop never runs because it directly follows another loop"
+            }
+            LintKind::RedundantClear => "this clears a cell that is already zero",
+            LintKind::CancellingPair => "these two instructions cancel each other out",
+            LintKind::UnreachableCode => {
+                "this code is unreachable because the loop before never ends"
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Lint {
+    pub kind: LintKind,
+    pub location: Location,
+}
+
+impl Lint {
+    pub fn severity(&self) -> Severity {
+        self.kind.severity()
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "{}:{}: {}: {}",
+            self.location.line,
+            self.location.column,
+            self.severity(),
+            self.kind.message()
+        )
+    }
+}
+
+/// Finds suspicious constructs in parsed code, ordered by their location
+pub fn lint_code(code: &[Node]) -> Vec<Lint> {
+    let mut lints = vec![];
+    let mut tape = Tape {
+        pos: 0,
+        known: HashMap::new(),
+        rest_zero: true,
+    };
+
+    lint_block(code, Location::default(), &mut tape, &mut lints);
+    lints.sort_by_key(|l| l.location);
+    lints
+}
+
+/// What is known about the cells while linting a block, relative to where the block starts
+struct Tape {
+    pos: isize,
+    known: HashMap<isize, Option<u8>>,
+    /// Whether all cells that are not in `known` are zero
+    rest_zero: bool,
+}
+
+impl Tape {
+    fn unknown() -> Tape {
+        Tape {
+            pos: 0,
+            known: HashMap::new(),
+            rest_zero: false,
+        }
+    }
+
+    fn value(&self, offset: isize) -> Option<u8> {
+        match self.known.get(&(self.pos + offset)) {
+            Some(value) => *value,
+            None if self.rest_zero => Some(0),
+            None => None,
+        }
+    }
+
+    fn set(&mut self, offset: isize, value: Option<u8>) {
+        self.known.insert(self.pos + offset, value);
+    }
+
+    fn forget(&mut self) {
+        self.known.clear();
+        self.rest_zero = false;
+    }
+}
+
+fn is_clear(body: &[Node]) -> bool {
+    matches!(body, [Node::Inc(1, 0, false)] | [Node::Dec(1, 0, false)])
+}
+
+fn cancels(first: &Node, second: &Node) -> bool {
+    match (first, second) {
+        (Node::Inc(1, 0, false), Node::Dec(1, 0, false))
+        | (Node::Dec(1, 0, false), Node::Inc(1, 0, false)) => true,
+        (Node::Shift(a), Node::Shift(b)) => a + b == 0,
+        _ => false,
+    }
+}
+
+fn lint_block(code: &[Node], start: Location, tape: &mut Tape, lints: &mut Vec<Lint>) {
+    let mut location = start;
+    let mut previous: Option<(&Node, Location)> = None;
+    let mut unreachable = false;
+
+    for node in code {
+        let node_location = location;
+        location = location.after(node);
+
+        if let Node::Comment(_) = node {
+            continue;
+        }
+        if unreachable {
+            lints.push(Lint {
+                kind: LintKind::UnreachableCode,
+                location: node_location,
+            });
+            return;
+        }
+
+        if let Some((previous_node, previous_location)) = previous {
+            if cancels(previous_node, node) {
+                lints.push(Lint {
+                    kind: LintKind::CancellingPair,
+                    location: previous_location,
+                });
+                // Do not report a pair overlapping this one
+                previous = None;
+                apply(node, tape);
+                continue;
+            }
+        }
+
+        if let Some((offset, body)) = node.as_loop() {
+            let condition = tape.value(offset);
+
+            if condition == Some(0) {
+                let kind = match previous {
+                    _ if is_clear(body) => LintKind::RedundantClear,
+                    Some((Node::Conditional(_), _)) | Some((Node::Loop(_), _)) => {
+                        LintKind::LoopAfterLoop
+                    }
+                    _ => LintKind::LoopNeverRuns,
+                };
+                lints.push(Lint {
+                    kind,
+                    location: node_location,
+                });
+            } else {
+                lint_block(body, node_location.next(), &mut Tape::unknown(), lints);
+
+                let never_ends = written_cells(body)
+                    .map(|cells| !cells.contains(&offset))
+                    .unwrap_or(false);
+                if condition.is_some() && never_ends {
+                    unreachable = true;
+                }
+
+                tape.forget();
+                tape.set(offset, Some(0));
+            }
+        } else {
+            apply(node, tape);
+        }
+
+        previous = Some((node, node_location));
+    }
+}
+
+/// Updates the tape for nodes that are not loops
+fn apply(node: &Node, tape: &mut Tape) {
+    match *node {
+        Node::Shift(v) => tape.pos += v,
+        Node::Inc(v, offset, move_pointer) | Node::Dec(v, offset, move_pointer) => {
+            let value = tape.value(offset).map(|old| match *node {
+                Node::Inc(_, _, _) => old.wrapping_add(v),
+                _ => old.wrapping_sub(v),
+            });
+            tape.set(offset, value);
+            if move_pointer {
+                tape.pos += offset;
+            }
+        }
+        Node::Assign(v, offset, move_pointer) => {
+            tape.set(offset, Some(v));
+            if move_pointer {
+                tape.pos += offset;
+            }
+        }
+        Node::In(offset, move_pointer) => {
+            tape.set(offset, None);
+            if move_pointer {
+                tape.pos += offset;
+            }
+        }
+        Node::Out(offset, move_pointer) => {
+            if move_pointer {
+                tape.pos += offset;
+            }
+        }
+        Node::Mul(_, into, offset, move_pointer) => {
+            tape.set(offset + into, None);
+            if move_pointer {
+                tape.pos += offset;
+            }
+        }
+        Node::Product(_, into, _, _) => tape.set(into, None),
+        Node::Scan(_) | Node::Conditional(_) | Node::Loop(_) => tape.forget(),
+        Node::Comment(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parser::parse_code;
+
+    fn lint(source: &str) -> Vec<(LintKind, usize, usize)> {
+        let parsed = parse_code(&mut source.as_bytes()).unwrap();
+
+        lint_code(&parsed)
+            .into_iter()
+            .map(|l| (l.kind, l.location.line, l.location.column))
+            .collect()
+    }
+
+    #[test]
+    fn it_should_not_warn_about_regular_code() {
+        assert_eq!(lint("++[>+++<-]>.,[.,]"), vec![]);
+    }
+
+    #[test]
+    fn it_should_warn_about_loops_that_never_run() {
+        assert_eq!(lint("[.]+"), vec![(LintKind::LoopNeverRuns, 1, 1)]);
+        assert_eq!(
+            lint("+>[.]<-[<]"),
+            vec![
+                (LintKind::LoopNeverRuns, 1, 3),
+                (LintKind::LoopNeverRuns, 1, 8)
+            ]
+        );
+    }
+
+    #[test]
+    fn it_should_warn_about_loops_after_loops() {
+        assert_eq!(lint(",[-<]\n[>.<]"), vec![(LintKind::LoopAfterLoop, 2, 1)]);
+        assert_eq!(lint(",[.,] [.]"), vec![(LintKind::LoopAfterLoop, 1, 7)]);
+    }
+
+    #[test]
+    fn it_should_note_cancelling_pairs() {
+        assert_eq!(
+            lint(",+-.>\n<"),
+            vec![
+                (LintKind::CancellingPair, 1, 2),
+                (LintKind::CancellingPair, 1, 5)
+            ]
+        );
+        assert_eq!(lint(",+-+."), vec![(LintKind::CancellingPair, 1, 2)]);
+    }
+
+    #[test]
+    fn it_should_note_redundant_clears() {
+        assert_eq!(
+            lint(">[-],[.[-]][+]"),
+            vec![
+                (LintKind::RedundantClear, 1, 2),
+                (LintKind::RedundantClear, 1, 12)
+            ]
+        );
+    }
+
+    #[test]
+    fn it_should_warn_about_unreachable_code() {
+        assert_eq!(
+            lint("+[>+<] comment\n."),
+            vec![(LintKind::UnreachableCode, 2, 1)]
+        );
+        assert_eq!(lint(",[>+<]."), vec![]);
+    }
+
+    #[test]
+    fn it_should_format_lints() {
+        let lint = Lint {
+            kind: LintKind::CancellingPair,
+            location: Location { line: 2, column: 3 },
+        };
+
+        assert_eq!(
+            format!("{}", lint),
+            "2:3: note: these two instructions cancel each other out"
+        );
+    }
+}
@@ -1,141 +1,405 @@
+extern crate dirs;
+extern crate ruinfuck;
 extern crate rustyline;
 
-pub mod analyzer;
-pub mod optimizer;
-pub mod parser;
-pub mod vm;
+mod cli;
+mod repl;
 
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 use std::env;
-use std::fs::File;
-use std::io::{self, BufRead, BufReader, Read, Write};
-
-// use analyzer::Analyzer;
-use parser::ParserError;
-use vm::{RuntimeError, State};
-
-#[derive(Debug, PartialEq)]
-pub enum ExecutionError {
-    Parse(ParserError),
-    Run(RuntimeError),
-}
-
-/// Run some brainfuck code
-pub fn run_code<F: BufRead, R: Read, W: Write>(
-    code: &mut F,
-    stdin: &mut R,
-    stdout: &mut W,
-    s: &mut State,
-) -> Result<(), ExecutionError> {
-    let parsed = parser::parse_code(code).map_err(ExecutionError::Parse)?;
-    let optimized = optimizer::optimize_code(&parsed, &optimizer::OptimizationOptions::default());
-
-    // println!(
-    //     "Unoptimized: {:?}",
-    //     (analyzer::SimpleAnalyzer {}).analyze(&parsed)
-    // );
-    // println!(
-    //     "Optimized: {:?}",
-    //     (analyzer::SimpleAnalyzer {}).analyze(&optimized)
-    // );
-    // println!("Code: {:?}", optimized);
-
-    vm::run_block(stdin, stdout, &optimized, s).map_err(ExecutionError::Run)
-}
-
-fn start_script(path: &str) -> Result<(), ExecutionError> {
-    let mut state = State::default();
-    let mut src_input = BufReader::new(File::open(path).map_err(|e| {
-        ExecutionError::Parse(ParserError::Io(format!(
-            "Could not open source file: {:?}",
-            e
-        )))
-    })?);
-    let stdin = io::stdin();
+use std::fmt::Write as FmtWrite;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::process;
+use std::time::{Duration, Instant};
+
+use cli::{CellWidth, CliError, Command, Options, Source, TapeKind};
+use repl::{Feedback, Repl, ReplHelper, INPUT_PROMPT};
+use ruinfuck::analyzer::{self, Analyzer};
+use ruinfuck::optimizer::{self, FilterComments, OptimizationOptions, OptimizationStep};
+use ruinfuck::tape::{HeapTape, SparseTape, Tape};
+use ruinfuck::vm::{self, Node, State, NUMBER_OF_CELLS};
+use ruinfuck::{encoding, format, ir, lint, parser, Program};
+
+fn program_source(options: &Options) -> Result<&Source, CliError> {
+    options
+        .source
+        .as_ref()
+        .ok_or_else(|| CliError::Usage("No program given".to_string()))
+}
+
+/// Reads the program from a file, stdin or the command line
+fn read_source(source: &Source) -> Result<Vec<u8>, CliError> {
+    match source {
+        Source::File(path) => fs::read(path)
+            .map_err(|e| CliError::Io(format!("Could not open source file {}: {}", path, e))),
+        Source::Stdin => {
+            let mut code = vec![];
+            io::stdin().read_to_end(&mut code).map_err(|e| {
+                CliError::Io(format!("Could not read the program from stdin: {}", e))
+            })?;
+            Ok(code)
+        }
+        Source::Inline(code) => Ok(code.clone().into_bytes()),
+    }
+}
+
+/// Where the program reads its input from
+fn open_input(options: &Options) -> Result<Box<dyn Read>, CliError> {
+    match options.input {
+        Some(ref path) => {
+            let file = File::open(path)
+                .map_err(|e| CliError::Io(format!("Could not open input file {}: {}", path, e)))?;
+            Ok(Box::new(BufReader::new(file)))
+        }
+        None => Ok(Box::new(io::stdin())),
+    }
+}
+
+fn write_stdout(output: &[u8]) -> Result<(), CliError> {
+    let mut stdout = io::stdout();
+    stdout.write_all(output).map_err(stdout_error)?;
+    flush_stdout(&mut stdout)
+}
+
+fn stdout_error(e: io::Error) -> CliError {
+    CliError::Io(format!("Could not write to stdout: {}", e))
+}
+
+/// Writes what is still buffered, which is everything after the last line break
+fn flush_stdout<W: Write>(stdout: &mut W) -> Result<(), CliError> {
+    stdout.flush().map_err(stdout_error)
+}
+
+fn initial_state<C: Tape>(options: &Options) -> Result<State<C>, CliError> {
+    let state = State::<C>::with_len(options.tape_size).ok_or_else(|| {
+        CliError::Usage(format!(
+            "A tape of {} cells is too large for this tape storage",
+            options.tape_size
+        ))
+    })?;
+
+    Ok(State {
+        eof: options.eof,
+        ..state
+    })
+}
+
+/// Calls a command that runs the program with the tape storage chosen on the command line
+macro_rules! on_tape {
+    ($command:ident, $options:expr) => {
+        // Parsing the options only allows 8-bit cells on the array tape
+        match ($options.tape_kind(), $options.cell_width) {
+            (TapeKind::Array, _) => $command::<[u8; NUMBER_OF_CELLS]>($options),
+            (TapeKind::Heap, CellWidth::U8) => $command::<HeapTape>($options),
+            (TapeKind::Heap, CellWidth::U16) => $command::<HeapTape<u16>>($options),
+            (TapeKind::Heap, CellWidth::U32) => $command::<HeapTape<u32>>($options),
+            (TapeKind::Sparse, CellWidth::U8) => $command::<SparseTape>($options),
+            (TapeKind::Sparse, CellWidth::U16) => $command::<SparseTape<u16>>($options),
+            (TapeKind::Sparse, CellWidth::U32) => $command::<SparseTape<u32>>($options),
+        }
+    };
+}
+
+fn parse_source(source: &Source) -> Result<Vec<Node>, CliError> {
+    parser::parse_code(&mut read_source(source)?.as_slice())
+        .map_err(|e| CliError::Parse(source.clone(), e))
+}
+
+/// The optimizations of the chosen level that are correct for the chosen cell width
+fn optimization_options(options: &Options) -> OptimizationOptions {
+    let optimizations = OptimizationOptions::level(options.level);
+    if options.cell_width == CellWidth::U8 {
+        optimizations
+    } else {
+        optimizations.for_wide_cells()
+    }
+}
+
+/// Compiles the program, or loads it if it was written by `compile`
+fn load_program(source: &Source, options: &Options) -> Result<Program, CliError> {
+    let code = read_source(source)?;
+
+    if encoding::is_encoded(&code) {
+        if options.cell_width != CellWidth::U8 {
+            return Err(CliError::Usage(format!(
+                "{} is optimized for 8-bit cells, run its source to use wider cells",
+                source
+            )));
+        }
+        let decoded = encoding::decode(&code).map_err(|e| CliError::Decode(source.clone(), e))?;
+        Ok(Program::from_optimized(decoded))
+    } else {
+        Program::compile(&mut code.as_slice(), &optimization_options(options))
+            .map_err(|e| CliError::Parse(source.clone(), e))
+    }
+}
+
+fn run_script<C: Tape>(options: &Options) -> Result<(), CliError> {
+    let source = program_source(options)?;
+    let program = load_program(source, options)?;
     let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    program
+        .run(
+            &mut open_input(options)?,
+            &mut stdout,
+            &mut initial_state::<C>(options)?,
+        )
+        .map_err(|e| CliError::Run(source.clone(), e))?;
+    flush_stdout(&mut stdout)
+}
+
+fn compile_script(options: &Options) -> Result<(), CliError> {
+    let program = load_program(program_source(options)?, options)?;
+    let compiled = if options.ir {
+        ir::print_ir(program.code()).into_bytes()
+    } else {
+        encoding::encode(program.code())
+    };
+
+    match options.output {
+        Some(ref path) => fs::write(path, compiled)
+            .map_err(|e| CliError::Io(format!("Could not write output file {}: {}", path, e))),
+        None => write_stdout(&compiled),
+    }
+}
+
+fn analyze_script<C: Tape>(options: &Options) -> Result<(), CliError> {
+    let source = program_source(options)?;
+    let parsed = parse_source(source)?;
+    let optimized = optimizer::optimize_code(&parsed, &optimization_options(options));
+    let mut comparison = analyzer::Comparison::new(&parsed, &optimized);
+
+    if options.run_profile {
+        let mut profile = analyzer::Profile::default();
+        let stdout = io::stdout();
+        // Like `bench`, the program gets no input at all instead of waiting for stdin
+        let mut input: Box<dyn Read> = match options.input {
+            Some(_) => open_input(options)?,
+            None => Box::new(io::empty()),
+        };
+
+        vm::run_block_traced(
+            &mut input,
+            &mut stdout.lock(),
+            &optimized,
+            &mut initial_state::<C>(options)?,
+            &mut profile,
+        )
+        .map_err(|e| CliError::Run(source.clone(), e))?;
+        comparison.profile = Some(profile);
+    }
 
-    run_code(
-        &mut src_input,
-        &mut stdin.lock(),
-        &mut stdout.lock(),
-        &mut state,
+    let mut report = comparison.to_string();
+    if options.loops {
+        report.push('\n');
+        let loop_reports = analyzer::LoopAnalyzer {}.analyze(&parsed);
+        for loop_report in loop_reports {
+            let _ = writeln!(report, "{}", loop_report);
+        }
+    }
+    if options.lint {
+        report.push('\n');
+        for lint in lint::lint_code(&parsed) {
+            let _ = writeln!(report, "{}", lint);
+        }
+    }
+
+    write_stdout(report.as_bytes())
+}
+
+fn format_script(options: &Options) -> Result<(), CliError> {
+    let source = program_source(options)?;
+    let parsed = parse_source(source)?;
+
+    // Parsed code never contains nodes without source
+    let unformattable = || CliError::Internal(format!("{} could not be formatted", source));
+
+    let formatted = if options.minify {
+        format::minify_code(&parsed)
+            .ok_or_else(unformattable)?
+            .into_bytes()
+    } else {
+        let formatted = format::format_code(&parsed, &options.format).ok_or_else(unformattable)?;
+
+        // Only whitespace and comments may change, check that before anything gets overwritten
+        let reparsed = parser::parse_code(&mut formatted.as_slice()).ok();
+        if reparsed.map(|code| FilterComments.apply(&code)) != Some(FilterComments.apply(&parsed)) {
+            return Err(CliError::Internal(format!(
+                "Formatting {} would change the program, nothing was written",
+                source
+            )));
+        }
+        formatted
+    };
+
+    match options.output {
+        Some(ref path) => fs::write(path, formatted)
+            .map_err(|e| CliError::Io(format!("Could not write output file {}: {}", path, e))),
+        None => write_stdout(&formatted),
+    }
+}
+
+/// Prints every node the VM executes to stderr
+struct PrintTracer;
+
+impl vm::Tracer for PrintTracer {
+    fn trace(&mut self, node: &Node) {
+        eprintln!("{}", ir::describe(node));
+    }
+}
+
+fn debug_script<C: Tape>(options: &Options) -> Result<(), CliError> {
+    let source = program_source(options)?;
+    let program = load_program(source, options)?;
+    let mut state = initial_state::<C>(options)?;
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+
+    let result = if options.trace {
+        vm::run_block_traced(
+            &mut open_input(options)?,
+            &mut stdout,
+            program.code(),
+            &mut state,
+            &mut PrintTracer,
+        )
+    } else {
+        program.run(&mut open_input(options)?, &mut stdout, &mut state)
+    };
+    let flushed = flush_stdout(&mut stdout);
+
+    eprintln!("\n{}", options.view.render(&state));
+    result.map_err(|e| CliError::Run(source.clone(), e))?;
+    flushed
+}
+
+fn bench_script<C: Tape>(options: &Options) -> Result<(), CliError> {
+    let source = program_source(options)?;
+    let program = load_program(source, options)?;
+    // Every run gets the same input, and none without an input file instead of waiting for stdin
+    let mut input = vec![];
+    if options.input.is_some() {
+        open_input(options)?
+            .read_to_end(&mut input)
+            .map_err(|e| CliError::Io(format!("Could not read the input: {}", e)))?;
+    }
+
+    let mut times = vec![];
+    for _ in 0..options.iterations {
+        let mut state = initial_state::<C>(options)?;
+        let start = Instant::now();
+        program
+            .run(&mut input.as_slice(), &mut io::sink(), &mut state)
+            .map_err(|e| CliError::Run(source.clone(), e))?;
+        times.push(start.elapsed());
+    }
+
+    let total: Duration = times.iter().sum();
+    let min = times.iter().min().cloned().unwrap_or_default();
+    let max = times.iter().max().cloned().unwrap_or_default();
+    write_stdout(
+        format!(
+            "{} runs: min {:?}, mean {:?}, max {:?}\n",
+            times.len(),
+            min,
+            total / times.len().max(1) as u32,
+            max
+        )
+        .as_bytes(),
     )
-    .expect("Error interpreting");
+}
 
-    Ok(())
+/// Where the repl keeps its history, creating the directory if needed
+fn history_path() -> Option<PathBuf> {
+    let directory = dirs::data_dir()?.join("ruinfuck");
+    fs::create_dir_all(&directory).ok()?;
+    Some(directory.join("history"))
 }
 
-fn start_repl() {
-    let mut rl = Editor::<()>::new();
-    let mut state = State::default();
-    let stdin = io::stdin();
+fn start_repl(options: &Options) {
+    let mut rl = Editor::<ReplHelper>::new();
+    rl.set_helper(Some(ReplHelper::new()));
+    let history = history_path();
+    if let Some(ref path) = history {
+        // There is no history on the first start
+        let _ = rl.load_history(path);
+    }
+    let mut repl = Repl::new(options);
+    // A separate editor keeps program input out of the history and completion
+    let mut input_rl = Editor::<()>::new();
     let stdout = io::stdout();
 
+    if repl.dump {
+        println!("{}", repl.render_state());
+    }
     loop {
-        println!("{}", state);
-        let readline = rl.readline("rf# ");
+        let readline = rl.readline(repl.prompt());
 
         match readline {
             Ok(line) => {
                 rl.add_history_entry(&line);
-                match run_code(
-                    &mut line.as_bytes(),
-                    &mut stdin.lock(),
-                    &mut stdout.lock(),
-                    &mut state,
-                ) {
-                    Ok(()) => {}
-                    Err(e) => println!("{:?}", e),
+                // Programs read their input at their own prompt, after everything they wrote so far
+                let mut prompt = || {
+                    let _ = io::stdout().flush();
+                    input_rl.readline(INPUT_PROMPT).ok()
+                };
+                match repl.feed(&line, &mut prompt, &mut stdout.lock()) {
+                    Ok(Feedback::Changed) if repl.dump => println!("{}", repl.render_state()),
+                    Ok(_) => {}
+                    Err(e) => println!("error: {}", e),
                 };
             }
-            Err(ReadlineError::Interrupted) => {
-                println!("Exiting");
-                break;
-            }
-            Err(ReadlineError::Eof) => {
+            Err(ReadlineError::Interrupted) if repl.cancel() => {}
+            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => {
                 println!("Exiting");
                 break;
             }
             Err(err) => {
-                println!("Error: {:?}", err);
+                println!("error: {}", err);
                 break;
             }
         }
     }
-}
 
-fn main() {
-    let first_arg = env::args().nth(1);
-
-    if let Some(path) = first_arg {
-        start_script(&path).map_err(|e| format!("{:?}", e)).unwrap();
-    } else {
-        start_repl();
+    if let Some(ref path) = history {
+        if let Err(e) = rl.save_history(path) {
+            println!("error: Could not save the history: {}", e);
+        }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
 
-    #[test]
-    fn it_should_return_parser_errors_when_running_code() {
-        let stdin = vec![];
-        let mut stdout = vec![];
-        let mut s = State::default();
+    let (command, options) = match cli::parse_args(&args) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, cli::USAGE);
+            process::exit(CliError::Usage(e).exit_code());
+        }
+    };
 
-        let code = "[[]";
-        let result = run_code(
-            &mut code.as_bytes(),
-            &mut stdin.as_slice(),
-            &mut stdout,
-            &mut s,
-        );
+    let result = match command {
+        Command::Run => on_tape!(run_script, &options),
+        Command::Repl => {
+            start_repl(&options);
+            Ok(())
+        }
+        Command::Compile => compile_script(&options),
+        Command::Analyze => on_tape!(analyze_script, &options),
+        Command::Fmt => format_script(&options),
+        Command::Debug => on_tape!(debug_script, &options),
+        Command::Bench => on_tape!(bench_script, &options),
+        Command::Help => write_stdout(format!("{}\n", cli::USAGE).as_bytes()),
+    };
 
-        assert_eq!(
-            result,
-            Err(ExecutionError::Parse(ParserError::MissingDelimiter))
-        );
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        process::exit(e.exit_code());
     }
 }
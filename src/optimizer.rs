@@ -1,27 +1,98 @@
 use std::default::Default;
 use vm::Node;
 
-/// Which optimizations to enable.
+/// Which optimizations to enable. The default enables every optimization except the elimination of
+/// dead stores, which is enabled from `level(2)` on.
 pub struct OptimizationOptions {
+    /// Whether cells are wider than 8 bits, see `for_wide_cells`
+    wide_cells: bool,
     collapsed_operators: bool,
     collapsed_assignments: bool,
     collapsed_offsets: bool,
     collapsed_loops: bool,
+    collapsed_nested_loops: bool,
     collapsed_scan_loops: bool,
+    eliminated_dead_stores: bool,
+    normalized_loops: bool,
 }
 
 impl Default for OptimizationOptions {
     fn default() -> Self {
         OptimizationOptions {
+            wide_cells: false,
             collapsed_operators: true,
             collapsed_assignments: true,
             collapsed_offsets: true,
             collapsed_loops: true,
+            collapsed_nested_loops: true,
             collapsed_scan_loops: true,
+            eliminated_dead_stores: false,
+            normalized_loops: true,
         }
     }
 }
 
+impl OptimizationOptions {
+    /// Only filters comments
+    pub fn none() -> Self {
+        OptimizationOptions {
+            wide_cells: false,
+            collapsed_operators: false,
+            collapsed_assignments: false,
+            collapsed_offsets: false,
+            collapsed_loops: false,
+            collapsed_nested_loops: false,
+            collapsed_scan_loops: false,
+            eliminated_dead_stores: false,
+            normalized_loops: false,
+        }
+    }
+
+    /// Level 0 only filters comments, level 1 also merges operators and collapses assignments and offsets,
+    /// level 2 and above enable all optimizations including the elimination of dead stores
+    pub fn level(level: u8) -> Self {
+        match level {
+            0 => OptimizationOptions::none(),
+            1 => OptimizationOptions {
+                collapsed_operators: true,
+                collapsed_assignments: true,
+                collapsed_offsets: true,
+                ..OptimizationOptions::none()
+            },
+            _ => OptimizationOptions {
+                eliminated_dead_stores: true,
+                ..OptimizationOptions::default()
+            },
+        }
+    }
+
+    /// Leaves out the optimizations that rely on cells wrapping around at 256, for cells wider than 8 bits.
+    /// `[-]` still becomes an assignment, but multiplication loops and dead stores stay as they are.
+    pub fn for_wide_cells(self) -> Self {
+        OptimizationOptions {
+            wide_cells: true,
+            ..self
+        }
+    }
+}
+
+/// Converts a factor modulo 256 into the smallest equivalent signed factor
+fn to_factor(value: u8) -> i16 {
+    if value > 128 {
+        i16::from(value) - 256
+    } else {
+        i16::from(value)
+    }
+}
+
+/// The multiplicative inverse of an odd number modulo 256
+fn inverse(value: u8) -> u8 {
+    // Every newton iteration doubles the number of correct bits, `value` itself is correct for 3 bits
+    (0..3).fold(value, |x, _| {
+        x.wrapping_mul(2u8.wrapping_sub(value.wrapping_mul(x)))
+    })
+}
+
 /// The trait implemented by every optimization step
 pub trait OptimizationStep {
     fn apply(&self, code: &[Node]) -> Vec<Node>;
@@ -140,8 +211,8 @@ impl OptimizationStep for CollapseAssignments {
                         Some(Node::Assign(value, offset1, false)),
                         Node::Mul(mul_value, into_offset, offset2, false),
                     ) => {
-                        if offset1 == offset2 && offset1 == into_offset {
-                            let abs = mul_value.abs() as u8;
+                        if offset1 == offset2 && *into_offset == 0 {
+                            let abs = mul_value.unsigned_abs() as u8;
 
                             Some(Node::Assign(
                                 if *mul_value >= 0 {
@@ -173,6 +244,26 @@ impl OptimizationStep for CollapseAssignments {
     }
 }
 
+/// The "Collapse Clear Loops" Optimization
+///
+/// Collapses `[-]` into `Assign(0)` instructions like "Collapse Assignments", but does not merge what comes
+/// after it into the assignment, since that would wrap around at 256 instead of the width of wider cells.
+pub struct CollapseClearLoops;
+
+impl OptimizationStep for CollapseClearLoops {
+    fn apply(&self, code: &[Node]) -> Vec<Node> {
+        code.iter()
+            .map(|n| match n {
+                Node::Conditional(body) if body == &[Node::Dec(1, 0, false)] => {
+                    Node::Assign(0, 0, false)
+                }
+                Node::Conditional(body) => Node::Conditional(self.apply(body)),
+                n => n.clone(),
+            })
+            .collect()
+    }
+}
+
 /// The "Collapse Offsets" Optimization
 ///
 /// Adds movement information to each instruction. Joins the operation and the adjacent movement into
@@ -316,6 +407,7 @@ impl OptimizationStep for DeferMovements {
                 | Node::Inc(_, _, _)
                 | Node::Dec(_, _, _)
                 | Node::Mul(_, _, _, _)
+                | Node::Product(_, _, _, _)
                 | Node::Assign(_, _, _)
                 | Node::In(_, _)
                 | Node::Out(_, _)
@@ -332,6 +424,15 @@ impl OptimizationStep for DeferMovements {
                     memo.push(vec![Node::Conditional(self.apply(body))]);
                     current_block = vec![];
                 }
+                Node::Loop(l) => {
+                    memo.push(current_block);
+                    memo.push(vec![Node::new_loop(
+                        l.offset,
+                        l.stride,
+                        self.apply(&l.body),
+                    )]);
+                    current_block = vec![];
+                }
             }
             (memo, current_block)
         });
@@ -375,6 +476,14 @@ impl OptimizationStep for DeferMovements {
                                 current_offset += offset;
                             }
                         }
+                        Node::Product(value, into, first, second) => {
+                            memo.push(Node::Product(
+                                value,
+                                current_offset + into,
+                                current_offset + first,
+                                current_offset + second,
+                            ));
+                        }
                         Node::In(offset, move_pointer) | Node::Out(offset, move_pointer) => {
                             let new_node = match node {
                                 Node::In(_, _) => Node::In,
@@ -389,6 +498,7 @@ impl OptimizationStep for DeferMovements {
                         }
                         Node::Comment(_) => {}
                         Node::Conditional(_) => {}
+                        Node::Loop(_) => {}
                         Node::Scan(_) => {}
                     }
                 }
@@ -408,24 +518,99 @@ impl OptimizationStep for DeferMovements {
 /// Introduces the multiplication instruction which is based on what is called a multiplication loop.
 /// When a loop fulfills the following conditions:
 ///
-/// - only contains incrementation and decrementation of the data pointer
+/// - only contains incrementation, decrementation and assignments of cells
 /// - does not actually move the data pointer within its body
-/// - and substracts 1 from the data pointer at the begining or end
+/// - changes the current cell by an odd amount in every iteration and never assigns it
 ///
-/// Then it is actually multiplying the current cell into one ore more other cells.
+/// Then it is actually multiplying the current cell into one ore more other cells. Since the step is odd it
+/// has an inverse modulo 256, which gives the number of iterations for every value of the current cell:
+/// `[+++>+<]` runs `85 * x` times and becomes `Mul(85, 1, 0, false), Assign(0, 0, false)`.
+///
+/// Assignments to other cells only depend on whether the loop runs at all, so a loop containing them is
+/// replaced by a conditional that runs exactly once.
 ///
 /// A brainfuck example: `[>>+++<<-]` becomes `Mul(3, 2, false), Assign(0, 0)`
 pub struct CollapseSimpleLoops;
 
 impl CollapseSimpleLoops {
     fn is_collapsible_loop(body: &[Node]) -> bool {
-        let has_only_allowed_elements = body.iter().fold(true, |memo, node| match node {
-            Node::Inc(_, _, false) => memo,
-            Node::Dec(_, _, false) => memo,
+        let has_only_allowed_elements = body.iter().all(|node| match node {
+            Node::Inc(_, _, false) | Node::Dec(_, _, false) => true,
+            Node::Assign(_, offset, false) => *offset != 0,
             _ => false,
         });
-        let contains_iterator = body.iter().any(|x| x == &Node::Dec(1, 0, false));
-        !body.is_empty() && has_only_allowed_elements && contains_iterator
+        !body.is_empty() && has_only_allowed_elements && Self::step(body) % 2 == 1
+    }
+
+    /// The amount the current cell changes by in every iteration of the loop
+    fn step(body: &[Node]) -> u8 {
+        body.iter().fold(0u8, |step, node| match node {
+            Node::Inc(value, 0, false) => step.wrapping_add(*value),
+            Node::Dec(value, 0, false) => step.wrapping_sub(*value),
+            _ => step,
+        })
+    }
+
+    fn collapse(body: &[Node]) -> Vec<Node> {
+        let iterations = inverse(Self::step(body)).wrapping_neg();
+        let assigned: Vec<isize> = body
+            .iter()
+            .filter_map(|node| match node {
+                Node::Assign(_, offset, _) => Some(*offset),
+                _ => None,
+            })
+            .collect();
+
+        let mut moves: Vec<Node> = body
+            .iter()
+            .enumerate()
+            .flat_map(|(index, node)| match node {
+                Node::Inc(_, 0, false) | Node::Dec(_, 0, false) => None,
+                Node::Inc(_, offset, false) | Node::Dec(_, offset, false)
+                    if assigned.contains(offset) =>
+                {
+                    None
+                }
+                Node::Inc(value, offset, false) => Some(Node::Mul(
+                    to_factor(value.wrapping_mul(iterations)),
+                    *offset,
+                    0,
+                    false,
+                )),
+                Node::Dec(value, offset, false) => Some(Node::Mul(
+                    to_factor(value.wrapping_mul(iterations).wrapping_neg()),
+                    *offset,
+                    0,
+                    false,
+                )),
+                Node::Assign(value, offset, false) => {
+                    let is_last_assignment = body[index + 1..].iter().all(|n| match n {
+                        Node::Assign(_, o, _) => o != offset,
+                        _ => true,
+                    });
+
+                    if is_last_assignment {
+                        let value = body[index + 1..].iter().fold(*value, |v, n| match n {
+                            Node::Inc(value, o, _) if o == offset => v.wrapping_add(*value),
+                            Node::Dec(value, o, _) if o == offset => v.wrapping_sub(*value),
+                            _ => v,
+                        });
+                        Some(Node::Assign(value, *offset, false))
+                    } else {
+                        None
+                    }
+                }
+                _ => None,
+            })
+            .collect();
+
+        moves.push(Node::Assign(0, 0, false));
+
+        if assigned.is_empty() {
+            moves
+        } else {
+            vec![Node::Conditional(moves)]
+        }
     }
 }
 
@@ -434,26 +619,10 @@ impl OptimizationStep for CollapseSimpleLoops {
         code.iter()
             .map(|node| match node {
                 Node::Conditional(body) => {
-                    if Self::is_collapsible_loop(&body) {
-                        let mut moves: Vec<Node> = body
-                            .iter()
-                            .flat_map(|node| match node {
-                                Node::Dec(1, 0, false) => None,
-                                Node::Inc(value, offset, false) => {
-                                    Some(Node::Mul(i16::from(*value), *offset, 0, false))
-                                }
-                                Node::Dec(value, offset, false) => {
-                                    Some(Node::Mul(-i16::from(*value), *offset, 0, false))
-                                }
-                                _ => None,
-                            })
-                            .collect();
-
-                        moves.push(Node::Assign(0, 0, false));
-
-                        moves
+                    if Self::is_collapsible_loop(body) {
+                        Self::collapse(body)
                     } else {
-                        vec![Node::Conditional(self.apply(&body))]
+                        vec![Node::Conditional(self.apply(body))]
                     }
                 }
                 n => vec![n.clone()],
@@ -467,6 +636,402 @@ impl OptimizationStep for CollapseSimpleLoops {
     }
 }
 
+/// An affine map `x -> Ax + b` modulo 256 over the cells at `offsets`
+#[derive(Clone, Debug, PartialEq)]
+struct AffineMap {
+    offsets: Vec<isize>,
+    matrix: Vec<Vec<u8>>,
+    constants: Vec<u8>,
+}
+
+impl AffineMap {
+    fn identity(offsets: Vec<isize>) -> Self {
+        let n = offsets.len();
+        AffineMap {
+            offsets,
+            matrix: (0..n)
+                .map(|i| (0..n).map(|j| if i == j { 1 } else { 0 }).collect())
+                .collect(),
+            constants: vec![0; n],
+        }
+    }
+
+    fn index(&self, offset: isize) -> usize {
+        self.offsets.iter().position(|o| *o == offset).unwrap()
+    }
+
+    /// Applies a single instruction after this map
+    fn apply(&mut self, node: &Node) {
+        match *node {
+            Node::Inc(value, offset, false) => {
+                let i = self.index(offset);
+                self.constants[i] = self.constants[i].wrapping_add(value);
+            }
+            Node::Dec(value, offset, false) => {
+                let i = self.index(offset);
+                self.constants[i] = self.constants[i].wrapping_sub(value);
+            }
+            Node::Assign(value, offset, false) => {
+                let i = self.index(offset);
+                self.matrix[i] = vec![0; self.offsets.len()];
+                self.constants[i] = value;
+            }
+            Node::Mul(value, into, offset, false) => {
+                let from = self.index(offset);
+                let to = self.index(offset + into);
+                let factor = value as u8;
+
+                for j in 0..self.offsets.len() {
+                    let v = self.matrix[from][j].wrapping_mul(factor);
+                    self.matrix[to][j] = self.matrix[to][j].wrapping_add(v);
+                }
+                let v = self.constants[from].wrapping_mul(factor);
+                self.constants[to] = self.constants[to].wrapping_add(v);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Composes this map with `other`, which is applied afterwards
+    fn then(&self, other: &AffineMap) -> AffineMap {
+        let n = self.offsets.len();
+        let mut result = other.clone();
+
+        for i in 0..n {
+            for j in 0..n {
+                result.matrix[i][j] = (0..n).fold(0u8, |acc, k| {
+                    acc.wrapping_add(other.matrix[i][k].wrapping_mul(self.matrix[k][j]))
+                });
+            }
+            result.constants[i] = (0..n).fold(other.constants[i], |acc, k| {
+                acc.wrapping_add(other.matrix[i][k].wrapping_mul(self.constants[k]))
+            });
+        }
+
+        result
+    }
+
+    fn difference(&self, other: &AffineMap) -> AffineMap {
+        let mut result = self.clone();
+
+        for (i, row) in result.matrix.iter_mut().enumerate() {
+            for (j, v) in row.iter_mut().enumerate() {
+                *v = v.wrapping_sub(other.matrix[i][j]);
+            }
+            result.constants[i] = result.constants[i].wrapping_sub(other.constants[i]);
+        }
+
+        result
+    }
+}
+
+/// The "Collapse Nested Loops" Optimization
+///
+/// Collapses loops that are left over after the simple loops have been collapsed and only consist of
+/// multiplications, assignments and incrementations of other cells, while the current cell is only
+/// changed by an odd step. Such a loop body is an affine map `f` over the cells it touches. When every
+/// iteration after the first one adds the same amount to the cells, i.e. `f³ - f² = f² - f`, running
+/// the loop `n` times equals `(2f - f²) + n(f² - f)`, which only needs multiplications and products of
+/// two cells.
+///
+/// Since this closed form only holds when the loop runs at least once, the result is a conditional
+/// that runs exactly once.
+///
+/// A brainfuck example: `[>[>+>+<<-]>>[<<+>>-]<<<-]` becomes
+/// `[Product(1, 2, 0, 1), Mul(-1, -1, 3), Product(1, 2, 0, 3), Mul(1, -2, 3), Assign(0, 3), Assign(0, 0)]`
+pub struct CollapseNestedLoops;
+
+impl CollapseNestedLoops {
+    fn affine_map(body: &[Node]) -> Option<(AffineMap, u8)> {
+        let mut offsets = vec![];
+        let mut step = 0u8;
+        let mut multiplies = false;
+
+        for node in body {
+            match *node {
+                Node::Inc(value, 0, false) => step = step.wrapping_add(value),
+                Node::Dec(value, 0, false) => step = step.wrapping_sub(value),
+                Node::Inc(_, offset, false)
+                | Node::Dec(_, offset, false)
+                | Node::Assign(_, offset, false)
+                    if offset != 0 =>
+                {
+                    offsets.push(offset)
+                }
+                Node::Mul(_, into, offset, false) if offset != 0 && offset + into != 0 => {
+                    multiplies = true;
+                    offsets.push(offset);
+                    offsets.push(offset + into);
+                }
+                _ => return None,
+            }
+        }
+        if !multiplies || step % 2 != 1 {
+            return None;
+        }
+
+        offsets.sort();
+        offsets.dedup();
+
+        let mut map = AffineMap::identity(offsets);
+        for node in body {
+            match node {
+                Node::Inc(_, 0, _) | Node::Dec(_, 0, _) => {}
+                n => map.apply(n),
+            }
+        }
+
+        Some((map, step))
+    }
+
+    fn collapse(body: &[Node]) -> Option<Vec<Node>> {
+        let (once, step) = Self::affine_map(body)?;
+        let twice = once.then(&once);
+        let thrice = twice.then(&once);
+        let increase = twice.difference(&once);
+
+        if thrice.difference(&twice) != increase {
+            return None;
+        }
+
+        let iterations = inverse(step).wrapping_neg();
+        let base = once.difference(&increase);
+        let n = once.offsets.len();
+
+        // The instructions updating every cell, together with the other cells they read from
+        let mut updates: Vec<(usize, Vec<Node>, Vec<usize>)> = vec![];
+        for i in 0..n {
+            let offset = once.offsets[i];
+            let is_identity = (0..n).all(|j| {
+                base.matrix[i][j] == if i == j { 1 } else { 0 } && increase.matrix[i][j] == 0
+            }) && base.constants[i] == 0
+                && increase.constants[i] == 0;
+
+            if is_identity {
+                continue;
+            }
+            if increase.matrix[i][i] != 0 {
+                return None;
+            }
+
+            let mut nodes = vec![];
+            if base.matrix[i][i] == 0 {
+                nodes.push(Node::Assign(base.constants[i], offset, false));
+            } else {
+                if base.matrix[i][i] != 1 {
+                    nodes.push(Node::Mul(
+                        to_factor(base.matrix[i][i].wrapping_sub(1)),
+                        0,
+                        offset,
+                        false,
+                    ));
+                }
+                if base.constants[i] != 0 {
+                    nodes.push(Node::Inc(base.constants[i], offset, false));
+                }
+            }
+
+            let mut reads = vec![];
+            for j in (0..n).filter(|j| *j != i) {
+                let other = once.offsets[j];
+
+                if base.matrix[i][j] != 0 {
+                    nodes.push(Node::Mul(
+                        to_factor(base.matrix[i][j]),
+                        offset - other,
+                        other,
+                        false,
+                    ));
+                }
+                if increase.matrix[i][j] != 0 {
+                    nodes.push(Node::Product(
+                        to_factor(increase.matrix[i][j].wrapping_mul(iterations)),
+                        offset,
+                        0,
+                        other,
+                    ));
+                }
+                if base.matrix[i][j] != 0 || increase.matrix[i][j] != 0 {
+                    reads.push(j);
+                }
+            }
+            if increase.constants[i] != 0 {
+                nodes.push(Node::Mul(
+                    to_factor(increase.constants[i].wrapping_mul(iterations)),
+                    offset,
+                    0,
+                    false,
+                ));
+            }
+
+            updates.push((i, nodes, reads));
+        }
+
+        // Every cell has to be updated before the cells it reads from are overwritten
+        let mut result = vec![];
+        while !updates.is_empty() {
+            let next = (0..updates.len()).find(|u| {
+                let cell = updates[*u].0;
+                updates.iter().all(|(_, _, reads)| !reads.contains(&cell))
+            })?;
+            let (_, nodes, _) = updates.remove(next);
+            result.extend(nodes);
+        }
+        result.push(Node::Assign(0, 0, false));
+
+        Some(vec![Node::Conditional(result)])
+    }
+}
+
+impl OptimizationStep for CollapseNestedLoops {
+    fn apply(&self, code: &[Node]) -> Vec<Node> {
+        code.iter()
+            .flat_map(|node| match node {
+                Node::Conditional(body) => {
+                    let body = self.apply(body);
+                    Self::collapse(&body).unwrap_or_else(|| vec![Node::Conditional(body)])
+                }
+                n => vec![n.clone()],
+            })
+            .collect()
+    }
+}
+
+/// The "Eliminate Dead Stores" Optimization
+///
+/// Looks at straight-line blocks of instructions that do not move the data pointer and follows every
+/// write to a cell to the next instruction that accesses the same cell. When that instruction is an
+/// assignment, the first write is dead and removed. Reading input does not count as overwriting the cell,
+/// since it may keep its value at the end of the input. When both are incrementations or decrementations, or an
+/// assignment followed by an incrementation, they are merged, even if other cells are accessed in between.
+///
+/// For example `Assign(0, 2), Out(0), Inc(3, 2), Assign(1, 1), Assign(2, 1)` becomes
+/// `Assign(3, 2), Out(0), Assign(2, 1)`.
+pub struct EliminateDeadStores;
+
+impl EliminateDeadStores {
+    /// The cell a node writes to
+    fn writes(node: &Node) -> Option<isize> {
+        match *node {
+            Node::Inc(_, offset, false)
+            | Node::Dec(_, offset, false)
+            | Node::Assign(_, offset, false)
+            | Node::In(offset, false) => Some(offset),
+            Node::Mul(_, into, offset, false) => Some(offset + into),
+            Node::Product(_, into, _, _) => Some(into),
+            _ => None,
+        }
+    }
+
+    fn accesses(node: &Node, cell: isize) -> bool {
+        match *node {
+            Node::Out(offset, false) => offset == cell,
+            Node::Mul(_, _, offset, false) => offset == cell || Self::writes(node) == Some(cell),
+            Node::Product(_, into, first, second) => {
+                into == cell || first == cell || second == cell
+            }
+            _ => Self::writes(node) == Some(cell),
+        }
+    }
+
+    fn is_straight_line(node: &Node) -> bool {
+        match node {
+            Node::Out(_, false) => true,
+            n => Self::writes(n).is_some(),
+        }
+    }
+
+    /// Combines a write with the next access of the same cell, `None` means the first write is dead
+    fn combine(first: &Node, next: &Node) -> Option<Option<Node>> {
+        match (first, next) {
+            // Reading leaves the cell as it was at the end of the input with `EofPolicy::Unchanged`
+            (Node::In(_, _), _) | (_, Node::In(_, _)) => None,
+            (_, Node::Assign(_, _, _)) => Some(None),
+            (Node::Assign(v, offset, _), Node::Inc(w, _, _)) => {
+                Some(Some(Node::Assign(v.wrapping_add(*w), *offset, false)))
+            }
+            (Node::Assign(v, offset, _), Node::Dec(w, _, _)) => {
+                Some(Some(Node::Assign(v.wrapping_sub(*w), *offset, false)))
+            }
+            (Node::Inc(v, offset, _), Node::Inc(w, _, _))
+            | (Node::Dec(v, offset, _), Node::Dec(w, _, _)) => {
+                let build_node = match first {
+                    Node::Inc(_, _, _) => Node::Inc,
+                    _ => Node::Dec,
+                };
+                Some(Some(build_node(v.wrapping_add(*w), *offset, false)))
+            }
+            (Node::Inc(v, offset, _), Node::Dec(w, _, _))
+            | (Node::Dec(v, offset, _), Node::Inc(w, _, _)) => {
+                let build_node = match first {
+                    Node::Inc(_, _, _) => Node::Inc,
+                    _ => Node::Dec,
+                };
+                Some(Some(build_node(v.wrapping_sub(*w), *offset, false)))
+            }
+            _ => None,
+        }
+    }
+
+    fn eliminate(block: &mut Vec<Node>) {
+        let mut i = 0;
+
+        while i < block.len() {
+            let cell = match Self::writes(&block[i]) {
+                Some(cell) => cell,
+                None => {
+                    i += 1;
+                    continue;
+                }
+            };
+            let next = (i + 1..block.len()).find(|j| Self::accesses(&block[*j], cell));
+
+            match next.and_then(|j| Self::combine(&block[i], &block[j]).map(|c| (j, c))) {
+                Some((_, None)) => {
+                    block.remove(i);
+                }
+                Some((j, Some(combined))) => {
+                    block.remove(j);
+                    match combined {
+                        Node::Inc(0, _, _) | Node::Dec(0, _, _) => {
+                            block.remove(i);
+                        }
+                        n => block[i] = n,
+                    }
+                }
+                None => {
+                    i += 1;
+                }
+            }
+        }
+    }
+}
+
+impl OptimizationStep for EliminateDeadStores {
+    fn apply(&self, code: &[Node]) -> Vec<Node> {
+        let mut result = vec![];
+        let mut block = vec![];
+
+        for node in code {
+            if Self::is_straight_line(node) {
+                block.push(node.clone());
+                continue;
+            }
+
+            Self::eliminate(&mut block);
+            result.append(&mut block);
+            match node {
+                Node::Conditional(body) => result.push(Node::Conditional(self.apply(body))),
+                n => result.push(n.clone()),
+            }
+        }
+        Self::eliminate(&mut block);
+        result.append(&mut block);
+
+        result
+    }
+}
+
 /// The "Collapse Scan Loops" Optimization
 ///
 /// Introduces the scan instruction which searches for the next zero to the left or right of the data pointer.
@@ -490,6 +1055,145 @@ impl OptimizationStep for CollapseScanLoops {
     }
 }
 
+/// The data pointer movement of a block of code, if it is the same every time the block runs
+pub fn stride(code: &[Node]) -> Option<isize> {
+    code.iter().try_fold(0, |stride, node| match node {
+        Node::Shift(v) => Some(stride + v),
+        Node::Inc(_, offset, true)
+        | Node::Dec(_, offset, true)
+        | Node::Mul(_, _, offset, true)
+        | Node::Assign(_, offset, true)
+        | Node::Out(offset, true)
+        | Node::In(offset, true) => Some(stride + offset),
+        Node::Conditional(body) => match self::stride(body) {
+            Some(0) => Some(stride),
+            _ => None,
+        },
+        Node::Loop(l) if l.stride == 0 => Some(stride),
+        Node::Loop(_) | Node::Scan(_) => None,
+        _ => Some(stride),
+    })
+}
+
+/// The "Normalize Loops" Optimization
+///
+/// Defers movements of the data pointer across loops with a fixed stride, by running the loop at an offset
+/// and moving all accesses in its body by the same offset. In the end all accesses of a balanced loop body
+/// are offsets from the data pointer at the start of the loop. Loops that move the data pointer by a fixed
+/// amount every iteration are annotated with their stride.
+///
+/// For example `Shift(2), [Inc(1, 0), Shift(1)], Out(0)` becomes `Loop(2, 1, [Inc(1, 2), Shift(1)]), Out(2)`,
+/// `[Shift(2), [Dec(1, 0)], Shift(-2)]` becomes `[Loop(2, 0, [Dec(1, 2)])]`.
+pub struct NormalizeLoops;
+
+impl NormalizeLoops {
+    /// Moves every access of a node by `delta`
+    fn shift(node: &Node, delta: isize) -> Vec<Node> {
+        match *node {
+            Node::Inc(v, offset, move_pointer) => vec![Node::Inc(v, offset + delta, move_pointer)],
+            Node::Dec(v, offset, move_pointer) => vec![Node::Dec(v, offset + delta, move_pointer)],
+            Node::Assign(v, offset, move_pointer) => {
+                vec![Node::Assign(v, offset + delta, move_pointer)]
+            }
+            Node::Mul(v, into, offset, move_pointer) => {
+                vec![Node::Mul(v, into, offset + delta, move_pointer)]
+            }
+            Node::Product(v, into, first, second) => {
+                vec![Node::Product(
+                    v,
+                    into + delta,
+                    first + delta,
+                    second + delta,
+                )]
+            }
+            Node::Out(offset, move_pointer) => vec![Node::Out(offset + delta, move_pointer)],
+            Node::In(offset, move_pointer) => vec![Node::In(offset + delta, move_pointer)],
+            Node::Loop(ref l) => vec![Node::new_loop(
+                l.offset + delta,
+                l.stride,
+                l.body.iter().flat_map(|n| Self::shift(n, delta)).collect(),
+            )],
+            Node::Conditional(ref body) => match self::stride(body) {
+                Some(stride) => vec![Node::new_loop(
+                    delta,
+                    stride,
+                    body.iter().flat_map(|n| Self::shift(n, delta)).collect(),
+                )],
+                None => vec![
+                    Node::Shift(delta),
+                    Node::Conditional(body.clone()),
+                    Node::Shift(-delta),
+                ],
+            },
+            Node::Scan(_) => vec![Node::Shift(delta), node.clone(), Node::Shift(-delta)],
+            Node::Shift(_) | Node::Comment(_) => vec![node.clone()],
+        }
+    }
+}
+
+impl OptimizationStep for NormalizeLoops {
+    fn apply(&self, code: &[Node]) -> Vec<Node> {
+        let mut result = vec![];
+        let mut current_offset: isize = 0;
+
+        for node in code {
+            match node {
+                Node::Shift(v) => {
+                    current_offset += v;
+                }
+                Node::Conditional(_) | Node::Loop(_) if matches!(node.as_loop(), Some((0, _))) => {
+                    let body = self.apply(node.as_loop().unwrap().1);
+
+                    match stride(&body) {
+                        Some(0) if current_offset == 0 => result.push(Node::Conditional(body)),
+                        Some(stride) => {
+                            result.append(&mut Self::shift(
+                                &Node::new_loop(0, stride, body),
+                                current_offset,
+                            ));
+                        }
+                        None => {
+                            if current_offset != 0 {
+                                result.push(Node::Shift(current_offset));
+                            }
+                            result.push(Node::Conditional(body));
+                            current_offset = 0;
+                        }
+                    }
+                }
+                Node::Scan(_) | Node::Loop(_) => {
+                    if current_offset != 0 {
+                        result.push(Node::Shift(current_offset));
+                    }
+                    result.push(node.clone());
+                    current_offset = 0;
+                }
+                n => {
+                    let (fixed, moved) = match *n {
+                        Node::Inc(v, offset, true) => (Node::Inc(v, offset, false), offset),
+                        Node::Dec(v, offset, true) => (Node::Dec(v, offset, false), offset),
+                        Node::Mul(v, into, offset, true) => {
+                            (Node::Mul(v, into, offset, false), offset)
+                        }
+                        Node::Assign(v, offset, true) => (Node::Assign(v, offset, false), offset),
+                        Node::Out(offset, true) => (Node::Out(offset, false), offset),
+                        Node::In(offset, true) => (Node::In(offset, false), offset),
+                        _ => (n.clone(), 0),
+                    };
+                    result.append(&mut Self::shift(&fixed, current_offset));
+                    current_offset += moved;
+                }
+            }
+        }
+
+        if current_offset != 0 {
+            result.push(Node::Shift(current_offset));
+        }
+
+        result
+    }
+}
+
 pub fn optimize_code(code: &[Node], options: &OptimizationOptions) -> Vec<Node> {
     let mut optimizations: Vec<Box<dyn OptimizationStep>> = vec![];
 
@@ -497,7 +1201,9 @@ pub fn optimize_code(code: &[Node], options: &OptimizationOptions) -> Vec<Node>
     if options.collapsed_operators {
         optimizations.push(Box::new(MergeRepeatedOperators));
     }
-    if options.collapsed_assignments {
+    if options.collapsed_assignments && options.wide_cells {
+        optimizations.push(Box::new(CollapseClearLoops));
+    } else if options.collapsed_assignments {
         optimizations.push(Box::new(CollapseAssignments));
     }
     if options.collapsed_offsets {
@@ -505,15 +1211,26 @@ pub fn optimize_code(code: &[Node], options: &OptimizationOptions) -> Vec<Node>
     }
     if options.collapsed_loops {
         optimizations.push(Box::new(DeferMovements));
-        optimizations.push(Box::new(CollapseSimpleLoops));
+        if !options.wide_cells {
+            optimizations.push(Box::new(CollapseSimpleLoops));
+        }
         if options.collapsed_offsets {
             optimizations.push(Box::new(CollapseOffsets));
         }
         optimizations.push(Box::new(DeferMovements));
+        if options.collapsed_nested_loops && !options.wide_cells {
+            optimizations.push(Box::new(CollapseNestedLoops));
+        }
+    }
+    if options.eliminated_dead_stores && !options.wide_cells {
+        optimizations.push(Box::new(EliminateDeadStores));
     }
     if options.collapsed_scan_loops {
         optimizations.push(Box::new(CollapseScanLoops));
     }
+    if options.normalized_loops {
+        optimizations.push(Box::new(NormalizeLoops));
+    }
 
     let mut c = code.to_owned();
     for o in &optimizations {
@@ -526,6 +1243,10 @@ pub fn optimize_code(code: &[Node], options: &OptimizationOptions) -> Vec<Node>
 #[cfg(test)]
 mod tests {
     use super::*;
+    use ir::parse_ir;
+    use parser::parse_code;
+    use tape::{HeapTape, Tape};
+    use vm::{run_block, EofPolicy, State};
 
     #[test]
     fn it_should_optimize_away_comments() {
@@ -544,11 +1265,8 @@ mod tests {
         assert_eq!(
             result,
             vec!(
-                Node::Shift(1),
-                Node::Conditional(vec!(
-                    Node::Shift(1),
-                    Node::Conditional(vec!(Node::Inc(1, 0, false),))
-                ))
+                Node::new_loop(1, 1, vec!(Node::Assign(0, 2, false), Node::Shift(1))),
+                Node::Shift(1)
             )
         );
     }
@@ -580,11 +1298,15 @@ mod tests {
         let result = optimize_code(
             &code,
             &OptimizationOptions {
+                wide_cells: false,
                 collapsed_scan_loops: false,
                 collapsed_operators: true,
                 collapsed_loops: false,
+                collapsed_nested_loops: false,
                 collapsed_assignments: false,
                 collapsed_offsets: false,
+                eliminated_dead_stores: false,
+                normalized_loops: false,
             },
         );
 
@@ -671,6 +1393,18 @@ mod tests {
         );
     }
 
+    #[test]
+    fn it_should_only_collapse_multiplications_into_the_assigned_cell() {
+        let into_same_cell = vec![Node::Assign(5, 2, false), Node::Mul(2, 0, 2, false)];
+        let into_other_cell = vec![Node::Assign(5, 2, false), Node::Mul(2, 2, 2, false)];
+
+        assert_eq!(
+            CollapseAssignments.apply(&into_same_cell),
+            vec![Node::Assign(15, 2, false)]
+        );
+        assert_eq!(CollapseAssignments.apply(&into_other_cell), into_other_cell);
+    }
+
     #[test]
     fn it_should_collapse_to_positive_offsets() {
         let code = vec![
@@ -696,11 +1430,15 @@ mod tests {
         let result = optimize_code(
             &code,
             &OptimizationOptions {
+                wide_cells: false,
                 collapsed_scan_loops: false,
                 collapsed_operators: false,
                 collapsed_loops: false,
+                collapsed_nested_loops: false,
                 collapsed_assignments: false,
                 collapsed_offsets: true,
+                eliminated_dead_stores: false,
+                normalized_loops: false,
             },
         );
 
@@ -746,11 +1484,15 @@ mod tests {
         let result = optimize_code(
             &code,
             &OptimizationOptions {
+                wide_cells: false,
                 collapsed_scan_loops: false,
                 collapsed_operators: false,
                 collapsed_loops: false,
+                collapsed_nested_loops: false,
                 collapsed_assignments: false,
                 collapsed_offsets: true,
+                eliminated_dead_stores: false,
+                normalized_loops: false,
             },
         );
 
@@ -784,11 +1526,15 @@ mod tests {
         let result = optimize_code(
             &code,
             &OptimizationOptions {
+                wide_cells: false,
                 collapsed_scan_loops: false,
                 collapsed_operators: false,
                 collapsed_loops: false,
+                collapsed_nested_loops: false,
                 collapsed_assignments: false,
                 collapsed_offsets: true,
+                eliminated_dead_stores: false,
+                normalized_loops: false,
             },
         );
 
@@ -804,11 +1550,15 @@ mod tests {
         let result = optimize_code(
             &code,
             &OptimizationOptions {
+                wide_cells: false,
                 collapsed_scan_loops: false,
                 collapsed_operators: false,
                 collapsed_loops: false,
+                collapsed_nested_loops: false,
                 collapsed_assignments: false,
                 collapsed_offsets: true,
+                eliminated_dead_stores: false,
+                normalized_loops: false,
             },
         );
 
@@ -821,11 +1571,15 @@ mod tests {
         let result = optimize_code(
             &code,
             &OptimizationOptions {
+                wide_cells: false,
                 collapsed_scan_loops: false,
                 collapsed_operators: false,
                 collapsed_loops: false,
+                collapsed_nested_loops: false,
                 collapsed_assignments: false,
                 collapsed_offsets: true,
+                eliminated_dead_stores: false,
+                normalized_loops: false,
             },
         );
 
@@ -838,11 +1592,15 @@ mod tests {
         let result = optimize_code(
             &code,
             &OptimizationOptions {
+                wide_cells: false,
                 collapsed_scan_loops: false,
                 collapsed_operators: false,
                 collapsed_loops: false,
+                collapsed_nested_loops: false,
                 collapsed_assignments: false,
                 collapsed_offsets: true,
+                eliminated_dead_stores: false,
+                normalized_loops: false,
             },
         );
 
@@ -855,11 +1613,15 @@ mod tests {
         let result = optimize_code(
             &code,
             &OptimizationOptions {
+                wide_cells: false,
                 collapsed_scan_loops: false,
                 collapsed_operators: false,
                 collapsed_loops: false,
+                collapsed_nested_loops: false,
                 collapsed_assignments: false,
                 collapsed_offsets: true,
+                eliminated_dead_stores: false,
+                normalized_loops: false,
             },
         );
 
@@ -879,11 +1641,15 @@ mod tests {
         let result = optimize_code(
             &code,
             &OptimizationOptions {
+                wide_cells: false,
                 collapsed_scan_loops: false,
                 collapsed_operators: false,
                 collapsed_loops: false,
+                collapsed_nested_loops: false,
                 collapsed_assignments: false,
                 collapsed_offsets: true,
+                eliminated_dead_stores: false,
+                normalized_loops: false,
             },
         );
 
@@ -923,11 +1689,15 @@ mod tests {
         let result = optimize_code(
             &code,
             &OptimizationOptions {
+                wide_cells: false,
                 collapsed_scan_loops: false,
                 collapsed_operators: false,
                 collapsed_loops: false,
+                collapsed_nested_loops: false,
                 collapsed_assignments: false,
                 collapsed_offsets: true,
+                eliminated_dead_stores: false,
+                normalized_loops: false,
             },
         );
 
@@ -961,11 +1731,15 @@ mod tests {
         let result = optimize_code(
             &code,
             &OptimizationOptions {
+                wide_cells: false,
                 collapsed_scan_loops: false,
                 collapsed_operators: false,
                 collapsed_loops: false,
+                collapsed_nested_loops: false,
                 collapsed_assignments: false,
                 collapsed_offsets: true,
+                eliminated_dead_stores: false,
+                normalized_loops: false,
             },
         );
 
@@ -1000,10 +1774,9 @@ mod tests {
                 Node::Inc(1, 10, false),
                 Node::Inc(1, 15, false),
                 Node::Mul(1, -5, 15, false),
-                Node::Shift(10),
-                Node::Conditional(vec!(Node::Dec(1, 5, false), Node::Out(0, false))),
-                Node::Inc(1, -5, false),
-                Node::Shift(-5),
+                Node::new_loop(10, 0, vec!(Node::Dec(1, 15, false), Node::Out(10, false))),
+                Node::Inc(1, 5, false),
+                Node::Shift(5),
             )
         );
     }
@@ -1041,6 +1814,364 @@ mod tests {
         );
     }
 
+    #[test]
+    fn it_should_collapse_simple_loops_with_odd_steps() {
+        let code = vec![
+            Node::Conditional(vec![Node::Inc(1, 0, false), Node::Inc(1, 1, false)]),
+            Node::Conditional(vec![Node::Inc(3, 0, false), Node::Dec(2, -1, false)]),
+        ];
+        let result = optimize_code(&code, &OptimizationOptions::default());
+
+        assert_eq!(
+            result,
+            vec!(
+                Node::Mul(-1, 1, 0, false),
+                Node::Assign(0, 0, false),
+                Node::Mul(86, -1, 0, false),
+                Node::Assign(0, 0, false),
+            )
+        );
+    }
+
+    #[test]
+    fn it_should_not_collapse_simple_loops_with_even_steps() {
+        let code = vec![Node::Conditional(vec![
+            Node::Dec(2, 0, false),
+            Node::Inc(1, 1, false),
+        ])];
+        let result = optimize_code(&code, &OptimizationOptions::default());
+
+        assert_eq!(
+            result,
+            vec!(Node::Conditional(vec!(
+                Node::Dec(2, 0, false),
+                Node::Inc(1, 1, false),
+            )))
+        );
+    }
+
+    #[test]
+    fn it_should_collapse_simple_loops_with_assignments_into_a_single_pass() {
+        let code = vec![Node::Conditional(vec![
+            Node::Inc(1, 1, false),
+            Node::Assign(2, 1, false),
+            Node::Inc(3, 1, false),
+            Node::Inc(1, 2, false),
+            Node::Dec(1, 0, false),
+        ])];
+        let result = optimize_code(&code, &OptimizationOptions::default());
+
+        assert_eq!(
+            result,
+            vec!(Node::Conditional(vec!(
+                Node::Assign(5, 1, false),
+                Node::Mul(1, 2, 0, false),
+                Node::Assign(0, 0, false),
+            )))
+        );
+    }
+
+    /// Runs `source` before and after optimizing it for every value of the current cell and compares
+    /// the results, `fill(i, counter)` sets up the cells around it
+    fn assert_equivalent(source: &str, fill: fn(usize, u8) -> u8) {
+        let parsed = parse_code(&mut source.as_bytes()).unwrap();
+        let optimized = optimize_code(&parsed, &OptimizationOptions::default());
+
+        for counter in 0..=255u8 {
+            let mut initial_state = State::default();
+            for i in 0..32 {
+                initial_state.cells[i] = fill(i, counter);
+            }
+            initial_state.pos = 8;
+            initial_state.cells[8] = counter;
+
+            let mut expected = initial_state.clone();
+            let mut actual = initial_state.clone();
+            run_block(&mut [].as_ref(), &mut vec![], &parsed, &mut expected).unwrap();
+            run_block(&mut [].as_ref(), &mut vec![], &optimized, &mut actual).unwrap();
+
+            assert_eq!(actual.pos, expected.pos, "{} with {}", source, counter);
+            assert_eq!(
+                actual.cells[0..32],
+                expected.cells[0..32],
+                "{} with {}",
+                source,
+                counter
+            );
+        }
+    }
+
+    #[test]
+    fn it_should_keep_values_above_255_when_optimizing_for_wide_cells() {
+        // 5 * 7 * 8 = 280 moved into the second cell, and -1 in the third
+        let source = "[-]+++++[>+++++++<-]>[<++++++++>-]<[>+<-]>>[-]-";
+        let parsed = parse_code(&mut source.as_bytes()).unwrap();
+
+        for level in 0..=2 {
+            let optimized =
+                optimize_code(&parsed, &OptimizationOptions::level(level).for_wide_cells());
+            let mut s = State::<HeapTape<u16>>::with_len(32).unwrap();
+            run_block(&mut [].as_ref(), &mut vec![], &optimized, &mut s).unwrap();
+
+            assert!(optimized
+                .iter()
+                .all(|n| !matches!(n, Node::Mul(_, _, _, _))));
+            assert_eq!(s.pos, 2);
+            assert_eq!(s.cells.nonzero(), vec![(1, 280), (2, u16::MAX)]);
+        }
+    }
+
+    #[test]
+    fn it_should_collapse_simple_loops_equivalently_for_all_counter_values() {
+        let sources = [
+            "[->+<]",
+            "[+>+<]",
+            "[+++>+<]",
+            "[--->++>--<<<+>]",
+            "[>+++<----->>-<<]",
+            "[>[-]+<-]",
+            "[>+>[-]++<[-]+++<+++]",
+        ];
+
+        for source in sources.iter() {
+            assert_equivalent(source, |i, _| (i as u8).wrapping_mul(37).wrapping_add(11));
+        }
+    }
+
+    #[test]
+    fn it_should_collapse_nested_loops() {
+        let code = parse_code(&mut "[>[>+<-]<-]".as_bytes()).unwrap();
+        let result = optimize_code(&code, &OptimizationOptions::default());
+
+        assert_eq!(
+            result,
+            vec!(Node::Conditional(vec!(
+                Node::Mul(1, 1, 1, false),
+                Node::Assign(0, 1, false),
+                Node::Assign(0, 0, false),
+            )))
+        );
+    }
+
+    #[test]
+    fn it_should_collapse_nested_loops_into_products() {
+        let code = parse_code(&mut "[>[>+>+<<-]>>[<<+>>-]<<<-]".as_bytes()).unwrap();
+        let result = optimize_code(&code, &OptimizationOptions::default());
+
+        assert_eq!(
+            result,
+            vec!(Node::Conditional(vec!(
+                Node::Product(1, 2, 0, 1),
+                Node::Mul(-1, -1, 3, false),
+                Node::Product(1, 2, 0, 3),
+                Node::Mul(1, -2, 3, false),
+                Node::Assign(0, 3, false),
+                Node::Assign(0, 0, false),
+            )))
+        );
+    }
+
+    #[test]
+    fn it_should_not_collapse_nested_loops_that_do_not_settle() {
+        let code = parse_code(&mut "[>[>+<-]>[<++>-]<<-]".as_bytes()).unwrap();
+        let result = optimize_code(&code, &OptimizationOptions::default());
+
+        assert!(match result.as_slice() {
+            [Node::Conditional(body)] => body.last() == Some(&Node::Dec(1, 0, false)),
+            _ => false,
+        });
+    }
+
+    #[test]
+    fn it_should_collapse_nested_loops_equivalently_for_all_counter_values() {
+        let sources = [
+            "[>[>+<-]<-]",
+            "[>[>+>+<<-]>>[<<+>>-]<<<-]",
+            "[>>[-]<[>+<-]+++<---]",
+            "[<[>>+<<-]>>[<+<+>>-]<-]",
+            "[>[>+<-]>[<++>-]<<-]",
+        ];
+
+        for source in sources.iter() {
+            assert_equivalent(source, |i, counter| {
+                (i as u8).wrapping_mul(37).wrapping_add(counter)
+            });
+        }
+    }
+
+    #[test]
+    fn it_should_eliminate_dead_stores() {
+        let code = vec![
+            Node::Assign(0, 2, false),
+            Node::Out(0, false),
+            Node::Inc(3, 2, false),
+            Node::Assign(1, 1, false),
+            Node::Mul(2, 1, 2, false),
+            Node::Assign(2, 3, false),
+            Node::Inc(1, 1, false),
+            Node::Out(1, false),
+            Node::Dec(1, 1, false),
+            Node::Assign(2, 1, false),
+        ];
+        let result = optimize_code(&code, &OptimizationOptions::level(2));
+
+        assert_eq!(
+            result,
+            vec!(
+                Node::Assign(3, 2, false),
+                Node::Out(0, false),
+                Node::Assign(2, 1, false),
+                Node::Assign(2, 3, false),
+                Node::Out(1, false),
+                Node::Assign(2, 1, false),
+            )
+        );
+    }
+
+    #[test]
+    fn it_should_merge_non_adjacent_operators_on_the_same_offset() {
+        let code = vec![
+            Node::Inc(3, 1, false),
+            Node::Dec(1, 0, false),
+            Node::Out(0, false),
+            Node::Dec(1, 1, false),
+            Node::Inc(1, 0, false),
+            Node::Inc(1, 2, false),
+            Node::Out(2, false),
+            Node::Dec(1, 2, false),
+        ];
+        let result = optimize_code(&code, &OptimizationOptions::level(2));
+
+        assert_eq!(
+            result,
+            vec!(
+                Node::Inc(2, 1, false),
+                Node::Dec(1, 0, false),
+                Node::Out(0, false),
+                Node::Inc(1, 0, false),
+                Node::Inc(1, 2, false),
+                Node::Out(2, false),
+                Node::Dec(1, 2, false),
+            )
+        );
+    }
+
+    #[test]
+    fn it_should_keep_stores_that_are_read_or_have_side_effects() {
+        let code = vec![
+            Node::Out(0, false),
+            Node::In(0, false),
+            Node::Assign(1, 0, false),
+            Node::Inc(1, 1, false),
+            Node::Conditional(vec![Node::Dec(1, 1, false)]),
+            Node::Assign(1, 1, false),
+            Node::Mul(2, 1, 1, false),
+            Node::Assign(1, 1, false),
+        ];
+        let result = optimize_code(&code, &OptimizationOptions::level(2));
+
+        assert_eq!(
+            result,
+            vec!(
+                Node::Out(0, false),
+                Node::In(0, false),
+                Node::Assign(1, 0, false),
+                Node::Inc(1, 1, false),
+                Node::Conditional(vec!(Node::Dec(1, 1, false))),
+                Node::Assign(1, 1, false),
+                Node::Mul(2, 1, 1, false),
+                Node::Assign(1, 1, false),
+            )
+        );
+    }
+
+    #[test]
+    fn it_should_keep_stores_before_reading_input_for_every_eof_policy() {
+        let policies = [
+            EofPolicy::Error,
+            EofPolicy::Zero,
+            EofPolicy::MinusOne,
+            EofPolicy::Unchanged,
+        ];
+
+        for (source, input) in [("+++,.", ""), (",,[-],.", "ab")].iter() {
+            let parsed = parse_code(&mut source.as_bytes()).unwrap();
+            let optimized = optimize_code(&parsed, &OptimizationOptions::level(2));
+
+            for eof in policies.iter() {
+                let run = |code: &[Node]| {
+                    let mut s = State {
+                        eof: *eof,
+                        ..State::default()
+                    };
+                    let mut stdout = vec![];
+                    let result = run_block(&mut input.as_bytes(), &mut stdout, code, &mut s);
+                    (result, stdout, s.cells[0])
+                };
+
+                assert_eq!(run(&optimized), run(&parsed), "{} with {:?}", source, eof);
+            }
+        }
+    }
+
+    #[test]
+    fn it_should_normalize_balanced_loop_bodies() {
+        let code = parse_code(&mut "+[>>[-<.>]<<-]".as_bytes()).unwrap();
+        let result = optimize_code(&code, &OptimizationOptions::default());
+
+        assert_eq!(
+            result,
+            vec!(
+                Node::Inc(1, 0, false),
+                Node::Conditional(vec!(
+                    Node::new_loop(2, 0, vec!(Node::Dec(1, 2, false), Node::Out(1, false))),
+                    Node::Dec(1, 0, false),
+                ))
+            )
+        );
+    }
+
+    #[test]
+    fn it_should_annotate_loops_with_their_stride() {
+        let code = parse_code(&mut "[>,.>]<[<<]>>[[-]>>,]".as_bytes()).unwrap();
+        let result = optimize_code(&code, &OptimizationOptions::default());
+        let expected = parse_ir(
+            "
+            loop @0 stride +2 {
+                in @+1
+                out @+1
+                shift +2
+            }
+            shift -1
+            scan -2
+            loop @+2 stride +2 {
+                assign 0 @+2
+                in @+4
+                shift +2
+            }
+            shift +2
+            ",
+        );
+
+        assert_eq!(Ok(result), expected);
+    }
+
+    #[test]
+    fn it_should_not_defer_movements_across_loops_without_a_stride() {
+        let code = parse_code(&mut ">[[>]>+<]>+".as_bytes()).unwrap();
+        let result = optimize_code(&code, &OptimizationOptions::default());
+
+        assert_eq!(
+            result,
+            vec!(
+                Node::Shift(1),
+                Node::Conditional(vec!(Node::Scan(1), Node::Inc(1, 1, false))),
+                Node::Inc(1, 1, false),
+                Node::Shift(1),
+            )
+        );
+    }
+
     #[test]
     fn it_should_collapse_scan_loops() {
         let code = vec![
@@ -1062,4 +2193,26 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn it_should_enable_optimizations_by_level() {
+        let code = parse_code(&mut "+++[->>+<<]#".as_bytes()).unwrap();
+
+        assert_eq!(
+            optimize_code(&code, &OptimizationOptions::level(0)),
+            parse_ir(
+                "inc 1 @0 inc 1 @0 inc 1 @0
+                 loop { dec 1 @0 shift +1 shift +1 inc 1 @0 shift -1 shift -1 }"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            optimize_code(&code, &OptimizationOptions::level(1)),
+            parse_ir("inc 3 @0 loop { dec 1 @0 inc 1 @+2 }").unwrap()
+        );
+        assert_eq!(
+            optimize_code(&code, &OptimizationOptions::level(2)),
+            parse_ir("inc 3 @0 mul 1 from @0 into @+2 assign 0 @0").unwrap()
+        );
+    }
 }
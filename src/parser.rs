@@ -1,3 +1,5 @@
+use std::error::Error;
+use std::fmt;
 use std::io::{BufRead, Read};
 
 use vm::Node;
@@ -18,29 +20,128 @@ impl From<char> for Node {
     }
 }
 
+/// A position in the source code, lines and columns start at 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Default for Location {
+    fn default() -> Self {
+        Location { line: 1, column: 1 }
+    }
+}
+
+impl Location {
+    /// The location right after the source of a parsed node that starts at this location
+    pub fn after(self, node: &Node) -> Location {
+        match node {
+            Node::Comment('\n') => Location {
+                line: self.line + 1,
+                column: 1,
+            },
+            Node::Conditional(body) => body
+                .iter()
+                .fold(self.next(), |location, n| location.after(n))
+                .next(),
+            _ => self.next(),
+        }
+    }
+
+    /// The location of the next character on the same line
+    pub fn next(self) -> Location {
+        Location {
+            line: self.line,
+            column: self.column + 1,
+        }
+    }
+
+    /// Resolves a location relative to this one, where this location is the start of the code the other
+    /// location refers to
+    pub fn join(self, relative: Location) -> Location {
+        if relative.line == 1 {
+            Location {
+                line: self.line,
+                column: self.column + relative.column - 1,
+            }
+        } else {
+            Location {
+                line: self.line + relative.line - 1,
+                column: relative.column,
+            }
+        }
+    }
+
+    /// The location of the node at `index` in parsed code starting at this location
+    pub fn of(self, code: &[Node], index: usize) -> Location {
+        code[..index]
+            .iter()
+            .fold(self, |location, n| location.after(n))
+    }
+}
+
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum ParserError {
-    UnmatchedDelimiter,
-    MissingDelimiter,
+    /// A `]` without a `[` before it
+    UnmatchedDelimiter(Location),
+    /// A `[` that is never closed
+    MissingDelimiter(Location),
     Io(String),
     Internal,
 }
 
+impl ParserError {
+    pub fn location(&self) -> Option<Location> {
+        match *self {
+            ParserError::UnmatchedDelimiter(location) | ParserError::MissingDelimiter(location) => {
+                Some(location)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ParserError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            ParserError::UnmatchedDelimiter(l) => {
+                write!(f, "{}:{}: this `]` has no matching `[`", l.line, l.column)
+            }
+            ParserError::MissingDelimiter(l) => {
+                write!(f, "{}:{}: this `[` is never closed", l.line, l.column)
+            }
+            ParserError::Io(e) => write!(f, "could not read the code: {}", e),
+            ParserError::Internal => f.write_str("internal parser error"),
+        }
+    }
+}
+
+impl Error for ParserError {}
+
 pub fn parse_code<F: BufRead>(code: &mut F) -> Result<Vec<Node>, ParserError> {
     let parsed = vec![];
     let mut nested = vec![parsed];
+    let mut opened = vec![];
+    let mut location = Location::default();
 
     for c in code.bytes() {
         let next_char = c.map_err(|e| ParserError::Io(format!("{}", e)))? as char;
 
         match next_char {
-            '[' => nested.push(vec![]),
+            '[' => {
+                nested.push(vec![]);
+                opened.push(location);
+            }
             ']' => {
                 if nested.len() < 2 {
-                    return Err(ParserError::UnmatchedDelimiter);
+                    return Err(ParserError::UnmatchedDelimiter(location));
                 }
 
                 let body = nested.pop().ok_or(ParserError::Internal)?;
+                opened.pop();
                 nested
                     .last_mut()
                     .ok_or(ParserError::Internal)?
@@ -51,10 +152,19 @@ pub fn parse_code<F: BufRead>(code: &mut F) -> Result<Vec<Node>, ParserError> {
                 .ok_or(ParserError::Internal)?
                 .push(Node::from(c)),
         }
+
+        location = if next_char == '\n' {
+            Location {
+                line: location.line + 1,
+                column: 1,
+            }
+        } else {
+            location.next()
+        };
     }
 
-    if nested.len() > 1 {
-        return Err(ParserError::MissingDelimiter);
+    if let Some(location) = opened.pop() {
+        return Err(ParserError::MissingDelimiter(location));
     }
     if nested.len() != 1 {
         return Err(ParserError::Internal);
@@ -123,19 +233,85 @@ mod tests {
         );
     }
 
+    #[test]
+    fn it_should_locate_nodes_in_the_source() {
+        let code = "+[\n>[-]\n ]-";
+        let parsed = parse_code(&mut code.as_bytes()).unwrap();
+
+        assert_eq!(
+            Location::default().of(&parsed, 0),
+            Location { line: 1, column: 1 }
+        );
+        assert_eq!(
+            Location::default().of(&parsed, 1),
+            Location { line: 1, column: 2 }
+        );
+        assert_eq!(
+            Location::default().of(&parsed, 2),
+            Location { line: 3, column: 3 }
+        );
+
+        if let Node::Conditional(body) = &parsed[1] {
+            let start = Location::default().of(&parsed, 1).next();
+            assert_eq!(start.of(body, 2), Location { line: 2, column: 2 });
+            assert_eq!(start.of(body, 4), Location { line: 3, column: 1 });
+        }
+    }
+
+    #[test]
+    fn it_should_join_relative_locations() {
+        let base = Location { line: 3, column: 4 };
+
+        assert_eq!(
+            base.join(Location { line: 1, column: 3 }),
+            Location { line: 3, column: 6 }
+        );
+        assert_eq!(
+            base.join(Location { line: 2, column: 3 }),
+            Location { line: 4, column: 3 }
+        );
+    }
+
     #[test]
     fn it_should_return_a_unmatched_delimiter_error() {
-        let code = "[]]";
+        let code = "[]\n+]";
         let result = parse_code(&mut code.as_bytes());
 
-        assert_eq!(result, Err(ParserError::UnmatchedDelimiter));
+        assert_eq!(
+            result,
+            Err(ParserError::UnmatchedDelimiter(Location {
+                line: 2,
+                column: 2
+            }))
+        );
     }
 
     #[test]
     fn it_should_return_a_missing_delimiter_error() {
-        let code = "[[]";
+        let code = "+[[]";
         let result = parse_code(&mut code.as_bytes());
 
-        assert_eq!(result, Err(ParserError::MissingDelimiter));
+        assert_eq!(
+            result,
+            Err(ParserError::MissingDelimiter(Location {
+                line: 1,
+                column: 2
+            }))
+        );
+    }
+
+    #[test]
+    fn it_should_format_errors_with_their_location() {
+        assert_eq!(
+            format!(
+                "{}",
+                ParserError::UnmatchedDelimiter(Location { line: 3, column: 7 })
+            ),
+            "3:7: this `]` has no matching `[`"
+        );
+        assert_eq!(
+            format!("{}", ParserError::Io("broken pipe".to_string())),
+            "could not read the code: broken pipe"
+        );
     }
 }
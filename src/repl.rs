@@ -0,0 +1,782 @@
+use rustyline::completion::{Completer, FilenameCompleter, Pair};
+use rustyline::highlight::{Highlighter, MatchingBracketHighlighter};
+use rustyline::hint::Hinter;
+use rustyline::{Context, Helper};
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+
+use cli::Options;
+use ruinfuck::optimizer::OptimizationOptions;
+use ruinfuck::view::{CellFormat, TapeView};
+use ruinfuck::vm::{EofPolicy, Node, State, NUMBER_OF_CELLS};
+use ruinfuck::{ir, ExecutionError, Program};
+
+const PROMPT: &str = "rf# ";
+/// Shown while the input so far has loops that are not closed yet
+const CONTINUATION_PROMPT: &str = "... ";
+/// Shown when a program reads input and there is no queued input
+pub const INPUT_PROMPT: &str = "in> ";
+
+/// How many states `:undo` can go back
+const UNDO_LIMIT: usize = 64;
+
+/// The first line of files written by `:save`
+const STATE_HEADER: &str = "# ruinfuck state";
+
+pub const HELP: &str = ":reset              Start over with an empty tape
+:load <file>        Run a brainfuck file, or restore a state written by :save
+:save <file>        Write the current state to a file
+:tape <from> <to>   Show the cells from one position to another
+:goto <pos>         Move the data pointer
+:set <pos> <value>  Change the value of a cell
+:opt on|off         Turn optimizations on or off
+:ir                 Show the optimized code of the last program
+:undo               Go back to the state before the last change
+:input \"<text>\"     Queue input for programs, supports \\n, \\t, \\\", \\\\ and \\xNN escapes
+:eof                End the input once the queued input is read
+:view <setting>     Show the state in decimal, hex or ascii, or change the window <size>,
+                    center <pos>|pointer, highlight on|off and compact on|off
+:state              Show the state
+:dump on|off        Show the state after every change or not
+:help               Show this help";
+
+/// The names of all commands, for completion
+const COMMANDS: &[&str] = &[
+    "reset", "load", "save", "tape", "goto", "set", "opt", "ir", "undo", "input", "eof", "view",
+    "state", "dump", "help",
+];
+
+#[derive(Debug, PartialEq)]
+pub enum ReplError {
+    Execution(ExecutionError),
+    Command(String),
+}
+
+impl fmt::Display for ReplError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            ReplError::Execution(e) => e.fmt(f),
+            ReplError::Command(e) => f.write_str(e),
+        }
+    }
+}
+
+/// What happened to a line of input
+#[derive(Debug, PartialEq)]
+pub enum Feedback {
+    /// The input has loops that are not closed yet
+    Pending,
+    /// Code ran or a command changed the state
+    Changed,
+    /// A command printed something without changing the state
+    Printed,
+}
+
+pub struct Repl {
+    state: State,
+    level: u8,
+    optimize: bool,
+    /// Whether the state is shown after every change
+    pub dump: bool,
+    view: TapeView,
+    /// Input collected until its brackets balance
+    pending: String,
+    /// The optimized code of the last program that ran
+    last: Option<Vec<Node>>,
+    undo: Vec<State>,
+    /// Input for programs that is not read yet
+    input: VecDeque<u8>,
+    /// Whether the input ends once the queue is empty
+    closed: bool,
+}
+
+impl Repl {
+    pub fn new(options: &Options) -> Repl {
+        Repl {
+            state: State {
+                eof: options.eof,
+                ..State::default()
+            },
+            level: options.level,
+            optimize: true,
+            dump: !options.no_dump,
+            view: options.view.clone(),
+            pending: String::new(),
+            last: None,
+            undo: vec![],
+            input: VecDeque::new(),
+            closed: false,
+        }
+    }
+
+    pub fn prompt(&self) -> &'static str {
+        if self.pending.is_empty() {
+            PROMPT
+        } else {
+            CONTINUATION_PROMPT
+        }
+    }
+
+    /// The tape as configured with `:view`
+    pub fn render_state(&self) -> String {
+        self.view.render(&self.state)
+    }
+
+    fn configure_view(&mut self, setting: &str, value: Option<&str>) -> Result<(), ReplError> {
+        let invalid = || {
+            ReplError::Command(format!(
+                "Invalid view setting `{} {}`",
+                setting,
+                value.unwrap_or("")
+            ))
+        };
+
+        match (setting, value) {
+            ("decimal", None) => self.view.format = CellFormat::Decimal,
+            ("hex", None) => self.view.format = CellFormat::Hex,
+            ("ascii", None) => self.view.format = CellFormat::Ascii,
+            ("window", Some(size)) => {
+                self.view.window = size.parse().ok().filter(|s| *s > 0).ok_or_else(invalid)?
+            }
+            ("center", Some("pointer")) => self.view.center = None,
+            ("center", Some(pos)) => self.view.center = Some(parse_position(pos)?),
+            ("highlight", Some(switch)) => self.view.highlight = parse_switch(switch)?,
+            ("compact", Some(switch)) => self.view.compact = parse_switch(switch)?,
+            _ => return Err(invalid()),
+        }
+        Ok(())
+    }
+
+    /// Handles a line of input, which is either a command starting with `:` or code. Code runs once the
+    /// brackets of all lines collected so far balance.
+    ///
+    /// Programs read their input from the queue filled with `:input`, and call `prompt` for another line
+    /// when it is empty. `prompt` returns `None` at the end of the input.
+    pub fn feed<P: FnMut() -> Option<String>, W: Write>(
+        &mut self,
+        line: &str,
+        prompt: &mut P,
+        stdout: &mut W,
+    ) -> Result<Feedback, ReplError> {
+        if self.pending.is_empty() && line.trim_start().starts_with(':') {
+            return self.command(line.trim(), prompt, stdout);
+        }
+
+        if !self.pending.is_empty() {
+            self.pending.push('\n');
+        }
+        self.pending.push_str(line);
+
+        if open_loops(&self.pending) > 0 {
+            return Ok(Feedback::Pending);
+        }
+
+        let code = ::std::mem::take(&mut self.pending);
+        self.run(&code, prompt, stdout)
+    }
+
+    /// Drops the lines collected so far, returns whether there were any
+    pub fn cancel(&mut self) -> bool {
+        let cancelled = !self.pending.is_empty();
+        self.pending.clear();
+        cancelled
+    }
+
+    fn run<P: FnMut() -> Option<String>, W: Write>(
+        &mut self,
+        code: &str,
+        prompt: &mut P,
+        stdout: &mut W,
+    ) -> Result<Feedback, ReplError> {
+        let level = if self.optimize { self.level } else { 0 };
+        let program = Program::compile(&mut code.as_bytes(), &OptimizationOptions::level(level))
+            .map_err(|e| ReplError::Execution(ExecutionError::Parse(e)))?;
+
+        self.remember();
+        self.last = Some(program.code().to_vec());
+        let mut input = ProgramInput {
+            queue: &mut self.input,
+            closed: &mut self.closed,
+            prompt,
+        };
+        program
+            .run(&mut input, stdout, &mut self.state)
+            .map_err(|e| ReplError::Execution(ExecutionError::Run(e)))?;
+        Ok(Feedback::Changed)
+    }
+
+    /// Keeps the current state for `:undo`
+    fn remember(&mut self) {
+        if self.undo.len() == UNDO_LIMIT {
+            self.undo.remove(0);
+        }
+        self.undo.push(self.state.clone());
+    }
+
+    fn command<P: FnMut() -> Option<String>, W: Write>(
+        &mut self,
+        line: &str,
+        prompt: &mut P,
+        stdout: &mut W,
+    ) -> Result<Feedback, ReplError> {
+        let (name, argument) = match line.find(char::is_whitespace) {
+            Some(i) => (&line[1..i], line[i..].trim()),
+            None => (&line[1..], ""),
+        };
+        let arguments: Vec<&str> = argument.split_whitespace().collect();
+        let print = |stdout: &mut W, text: &str| {
+            writeln!(stdout, "{}", text)
+                .map(|_| Feedback::Printed)
+                .map_err(|e| ReplError::Command(format!("Could not write output: {}", e)))
+        };
+
+        match (name, arguments.as_slice()) {
+            ("reset", []) => {
+                self.remember();
+                self.state = State {
+                    eof: self.state.eof,
+                    ..State::default()
+                };
+                Ok(Feedback::Changed)
+            }
+            ("load", _) if !argument.is_empty() => {
+                let content = fs::read_to_string(argument).map_err(|e| {
+                    ReplError::Command(format!("Could not read {}: {}", argument, e))
+                })?;
+
+                if content.starts_with(STATE_HEADER) {
+                    let state = parse_state(&content, self.state.eof).map_err(|e| {
+                        ReplError::Command(format!("Could not load {}: {}", argument, e))
+                    })?;
+                    self.remember();
+                    self.state = state;
+                    Ok(Feedback::Changed)
+                } else {
+                    self.run(&content, prompt, stdout)
+                }
+            }
+            ("save", _) if !argument.is_empty() => {
+                fs::write(argument, print_state(&self.state)).map_err(|e| {
+                    ReplError::Command(format!("Could not write {}: {}", argument, e))
+                })?;
+                Ok(Feedback::Printed)
+            }
+            ("tape", [from, to]) => {
+                let from = parse_position(from)?;
+                let to = parse_position(to)?;
+                if from > to {
+                    return Err(ReplError::Command(
+                        "The first position must not be after the second".to_string(),
+                    ));
+                }
+                let view = TapeView {
+                    columns: self.view.columns,
+                    format: self.view.format,
+                    highlight: self.view.highlight,
+                    ..TapeView::range(from, to)
+                };
+                print(stdout, &view.render(&self.state))
+            }
+            ("goto", [pos]) => {
+                let pos = parse_position(pos)?;
+                self.remember();
+                self.state.pos = pos;
+                Ok(Feedback::Changed)
+            }
+            ("set", [pos, value]) => {
+                let pos = parse_position(pos)?;
+                let value = value
+                    .parse()
+                    .map_err(|_| ReplError::Command(format!("Invalid cell value `{}`", value)))?;
+                self.remember();
+                self.state.cells[pos] = value;
+                Ok(Feedback::Changed)
+            }
+            ("opt", [switch]) => {
+                self.optimize = parse_switch(switch)?;
+                Ok(Feedback::Printed)
+            }
+            ("ir", []) => match self.last {
+                Some(ref code) => print(stdout, ir::print_ir(code).trim_end()),
+                None => Err(ReplError::Command("No program ran yet".to_string())),
+            },
+            ("undo", []) => match self.undo.pop() {
+                Some(state) => {
+                    self.state = state;
+                    Ok(Feedback::Changed)
+                }
+                None => Err(ReplError::Command("Nothing to undo".to_string())),
+            },
+            ("input", _) if !argument.is_empty() => {
+                self.input.extend(parse_quoted(argument)?);
+                self.closed = false;
+                Ok(Feedback::Printed)
+            }
+            ("eof", []) => {
+                self.closed = true;
+                Ok(Feedback::Printed)
+            }
+            ("view", [setting]) | ("view", [setting, _]) => {
+                self.configure_view(setting, arguments.get(1).cloned())?;
+                print(stdout, &self.render_state())
+            }
+            ("state", []) => print(stdout, &self.render_state()),
+            ("dump", [switch]) => {
+                self.dump = parse_switch(switch)?;
+                Ok(Feedback::Printed)
+            }
+            ("help", []) => print(stdout, HELP),
+            _ => Err(ReplError::Command(format!(
+                "Unknown command `{}`, :help lists all commands",
+                line
+            ))),
+        }
+    }
+}
+
+/// The input of programs running in the repl
+struct ProgramInput<'a, P: FnMut() -> Option<String> + 'a> {
+    queue: &'a mut VecDeque<u8>,
+    closed: &'a mut bool,
+    prompt: &'a mut P,
+}
+
+impl<'a, P: FnMut() -> Option<String>> Read for ProgramInput<'a, P> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.queue.is_empty() && !*self.closed {
+            match (self.prompt)() {
+                Some(line) => {
+                    self.queue.extend(line.bytes());
+                    self.queue.push_back(b'\n');
+                }
+                None => *self.closed = true,
+            }
+        }
+
+        let read = buf.len().min(self.queue.len());
+        for (target, byte) in buf.iter_mut().zip(self.queue.drain(..read)) {
+            *target = byte;
+        }
+        Ok(read)
+    }
+}
+
+/// Completes commands and the paths of files to load or save, and highlights the bracket matching the
+/// one at the cursor
+pub struct ReplHelper {
+    files: FilenameCompleter,
+    brackets: MatchingBracketHighlighter,
+}
+
+impl ReplHelper {
+    pub fn new() -> ReplHelper {
+        ReplHelper {
+            files: FilenameCompleter::new(),
+            brackets: MatchingBracketHighlighter::new(),
+        }
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        if line.starts_with(":load ") || line.starts_with(":save ") {
+            return self.files.complete_path(line, pos);
+        }
+
+        let candidates = complete_command(&line[..pos])
+            .into_iter()
+            .map(|command| Pair {
+                display: command.clone(),
+                replacement: command,
+            })
+            .collect();
+        Ok((0, candidates))
+    }
+}
+
+impl Hinter for ReplHelper {}
+
+impl Highlighter for ReplHelper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        self.brackets.highlight(line, pos)
+    }
+
+    fn highlight_char(&self, line: &str, pos: usize) -> bool {
+        self.brackets.highlight_char(line, pos)
+    }
+}
+
+impl Helper for ReplHelper {}
+
+/// The commands starting with what is typed before the cursor, with their colon
+fn complete_command(typed: &str) -> Vec<String> {
+    if !typed.starts_with(':') || typed.contains(char::is_whitespace) {
+        return vec![];
+    }
+
+    COMMANDS
+        .iter()
+        .filter(|command| command.starts_with(&typed[1..]))
+        .map(|command| format!(":{}", command))
+        .collect()
+}
+
+/// The number of loops that are opened but not closed, a `]` without a `[` makes the code complete so
+/// that parsing it reports the error
+fn open_loops(code: &str) -> usize {
+    let mut open = 0;
+
+    for c in code.chars() {
+        match c {
+            '[' => open += 1,
+            ']' if open == 0 => return 0,
+            ']' => open -= 1,
+            _ => {}
+        }
+    }
+    open
+}
+
+fn parse_position(pos: &str) -> Result<usize, ReplError> {
+    pos.parse()
+        .ok()
+        .filter(|p| *p < NUMBER_OF_CELLS)
+        .ok_or_else(|| ReplError::Command(format!("Invalid position `{}`", pos)))
+}
+
+/// Reads the bytes of a quoted string like `"a\\n"`
+fn parse_quoted(text: &str) -> Result<Vec<u8>, ReplError> {
+    let invalid = || {
+        ReplError::Command(format!(
+            "Expected a quoted string like \"text\\n\" instead of `{}`",
+            text
+        ))
+    };
+    if text.len() < 2 || !text.starts_with('"') || !text.ends_with('"') {
+        return Err(invalid());
+    }
+
+    let mut bytes = vec![];
+    let mut chars = text[1..text.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        let escaped = match c {
+            '"' => return Err(invalid()),
+            '\\' => chars.next().ok_or_else(invalid)?,
+            c => {
+                let mut buffer = [0; 4];
+                bytes.extend_from_slice(c.encode_utf8(&mut buffer).as_bytes());
+                continue;
+            }
+        };
+
+        match escaped {
+            'n' => bytes.push(b'\n'),
+            'r' => bytes.push(b'\r'),
+            't' => bytes.push(b'\t'),
+            '0' => bytes.push(0),
+            '\\' | '"' => bytes.push(escaped as u8),
+            'x' => {
+                let hex: String = chars.by_ref().take(2).collect();
+                let byte = u8::from_str_radix(&hex, 16).map_err(|_| invalid())?;
+                if hex.len() != 2 {
+                    return Err(invalid());
+                }
+                bytes.push(byte);
+            }
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(bytes)
+}
+
+fn parse_switch(switch: &str) -> Result<bool, ReplError> {
+    match switch {
+        "on" => Ok(true),
+        "off" => Ok(false),
+        _ => Err(ReplError::Command(format!(
+            "Expected `on` or `off` instead of `{}`",
+            switch
+        ))),
+    }
+}
+
+/// Writes the position and every cell that is not zero, one per line
+fn print_state(state: &State) -> String {
+    let mut text = format!("{}\npos {}\n", STATE_HEADER, state.pos);
+
+    for (i, v) in state.cells.iter().enumerate().filter(|(_, v)| **v != 0) {
+        text.push_str(&format!("{} {}\n", i, v));
+    }
+    text
+}
+
+fn parse_state(text: &str, eof: EofPolicy) -> Result<State, String> {
+    let mut state = State {
+        eof,
+        ..State::default()
+    };
+
+    for (number, line) in text.lines().enumerate().skip(1) {
+        let invalid = || format!("line {} is invalid", number + 1);
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        match words.as_slice() {
+            [] => {}
+            ["pos", pos] => {
+                state.pos = pos
+                    .parse()
+                    .ok()
+                    .filter(|p| *p < NUMBER_OF_CELLS)
+                    .ok_or_else(invalid)?
+            }
+            [cell, value] => {
+                let cell: usize = cell
+                    .parse()
+                    .ok()
+                    .filter(|c| *c < NUMBER_OF_CELLS)
+                    .ok_or_else(invalid)?;
+                state.cells[cell] = value.parse().map_err(|_| invalid())?;
+            }
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ruinfuck::parser::{Location, ParserError};
+    use ruinfuck::vm::RuntimeError;
+    use std::env;
+
+    fn feed(repl: &mut Repl, line: &str) -> Result<(Feedback, Vec<u8>), ReplError> {
+        let mut stdout = vec![];
+        repl.feed(line, &mut || None, &mut stdout)
+            .map(|feedback| (feedback, stdout))
+    }
+
+    fn output(repl: &mut Repl, line: &str) -> String {
+        String::from_utf8(feed(repl, line).unwrap().1).unwrap()
+    }
+
+    #[test]
+    fn it_should_collect_lines_until_the_brackets_balance() {
+        let mut repl = Repl::new(&Options::default());
+
+        assert_eq!(feed(&mut repl, "+++[>++"), Ok((Feedback::Pending, vec![])));
+        assert_eq!(repl.prompt(), CONTINUATION_PROMPT);
+        assert_eq!(feed(&mut repl, "[>+<-]"), Ok((Feedback::Pending, vec![])));
+        assert_eq!(feed(&mut repl, "<-]>>."), Ok((Feedback::Changed, vec![6])));
+        assert_eq!(repl.prompt(), PROMPT);
+        assert_eq!(feed(&mut repl, "<."), Ok((Feedback::Changed, vec![0])));
+    }
+
+    #[test]
+    fn it_should_report_unmatched_brackets_right_away() {
+        let mut repl = Repl::new(&Options::default());
+
+        assert_eq!(feed(&mut repl, "["), Ok((Feedback::Pending, vec![])));
+        assert_eq!(
+            feed(&mut repl, "]]"),
+            Err(ReplError::Execution(ExecutionError::Parse(
+                ParserError::UnmatchedDelimiter(Location { line: 2, column: 2 })
+            )))
+        );
+        assert_eq!(repl.prompt(), PROMPT);
+    }
+
+    #[test]
+    fn it_should_cancel_pending_lines() {
+        let mut repl = Repl::new(&Options::default());
+
+        assert_eq!(feed(&mut repl, "+["), Ok((Feedback::Pending, vec![])));
+        assert!(repl.cancel());
+        assert!(!repl.cancel());
+        assert_eq!(feed(&mut repl, "."), Ok((Feedback::Changed, vec![0])));
+    }
+
+    #[test]
+    fn it_should_change_the_state_with_commands() {
+        let mut repl = Repl::new(&Options::default());
+
+        feed(&mut repl, "+++>++").unwrap();
+        assert_eq!(feed(&mut repl, ":goto 10"), Ok((Feedback::Changed, vec![])));
+        assert_eq!(
+            feed(&mut repl, ":set 11 200"),
+            Ok((Feedback::Changed, vec![]))
+        );
+        assert_eq!(repl.state.pos, 10);
+        assert_eq!(repl.state.cells[11], 200);
+
+        feed(&mut repl, ":undo").unwrap();
+        assert_eq!(repl.state.cells[11], 0);
+        feed(&mut repl, ":undo").unwrap();
+        assert_eq!(repl.state.pos, 1);
+
+        feed(&mut repl, ":reset").unwrap();
+        assert_eq!(repl.state.cells[..2], [0, 0]);
+        feed(&mut repl, ":undo").unwrap();
+        assert_eq!(repl.state.cells[..2], [3, 2]);
+    }
+
+    #[test]
+    fn it_should_show_the_tape_and_the_optimized_code() {
+        let mut repl = Repl::new(&Options::default());
+
+        feed(&mut repl, "++>+++[-]").unwrap();
+        assert_eq!(
+            output(&mut repl, ":tape 0 2"),
+            "|     0|     1|     2|\n\
+             |     2|     0|     0|\n\
+             |      |******|      |\n"
+        );
+        assert_eq!(
+            output(&mut repl, ":ir"),
+            "inc 2 @0\nassign 0 @+1\nshift +1\n"
+        );
+
+        feed(&mut repl, ":opt off").unwrap();
+        feed(&mut repl, "+-").unwrap();
+        assert_eq!(output(&mut repl, ":ir"), "inc 1 @0\ndec 1 @0\n");
+    }
+
+    #[test]
+    fn it_should_configure_the_view() {
+        let mut repl = Repl::new(&Options::default());
+
+        feed(&mut repl, "+>++").unwrap();
+        feed(&mut repl, ":view window 3").unwrap();
+        feed(&mut repl, ":view center 0").unwrap();
+        assert_eq!(
+            output(&mut repl, ":view hex"),
+            "| 65535|     0|     1|\n\
+             |    00|    01|    02|\n\
+             |      |      |******|\n"
+        );
+        assert_eq!(
+            output(&mut repl, ":tape 1 1"),
+            "|     1|\n|    02|\n|******|\n"
+        );
+        assert_eq!(
+            feed(&mut repl, ":view window 0"),
+            Err(ReplError::Command(
+                "Invalid view setting `window 0`".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn it_should_save_and_load_states() {
+        let path = env::temp_dir().join("ruinfuck-repl-state-test");
+        let path = path.to_str().unwrap();
+        let mut repl = Repl::new(&Options::default());
+
+        feed(&mut repl, "+>>+++++<").unwrap();
+        feed(&mut repl, &format!(":save {}", path)).unwrap();
+        assert_eq!(
+            fs::read_to_string(path).unwrap(),
+            "# ruinfuck state\npos 1\n0 1\n2 5\n"
+        );
+
+        feed(&mut repl, ":reset").unwrap();
+        feed(&mut repl, &format!(":load {}", path)).unwrap();
+        assert_eq!(repl.state.pos, 1);
+        assert_eq!(repl.state.cells[..3], [1, 0, 5]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn it_should_read_queued_input_before_prompting() {
+        let mut repl = Repl::new(&Options::default());
+        let mut prompts = vec!["xy".to_string()];
+        let mut stdout = vec![];
+
+        feed(&mut repl, r#":input "a\x62""#).unwrap();
+        repl.feed(",.,.,.,.,.", &mut || prompts.pop(), &mut stdout)
+            .unwrap();
+
+        assert_eq!(stdout, b"abxy\n");
+        assert!(prompts.is_empty());
+    }
+
+    #[test]
+    fn it_should_end_the_input_with_the_eof_policy() {
+        let mut repl = Repl::new(&Options {
+            eof: EofPolicy::MinusOne,
+            ..Options::default()
+        });
+
+        feed(&mut repl, r#":input "\t""#).unwrap();
+        feed(&mut repl, ":eof").unwrap();
+        assert_eq!(
+            feed(&mut repl, ",.,."),
+            Ok((Feedback::Changed, vec![b'\t', 255]))
+        );
+
+        let mut repl = Repl::new(&Options::default());
+        feed(&mut repl, ":eof").unwrap();
+        assert_eq!(
+            feed(&mut repl, ","),
+            Err(ReplError::Execution(ExecutionError::Run(
+                RuntimeError::ReadError("No data from stdin".to_string())
+            )))
+        );
+    }
+
+    #[test]
+    fn it_should_parse_quoted_input() {
+        assert_eq!(
+            parse_quoted(r#""a\"\\\n\x00ü""#),
+            Ok(b"a\"\\\n\x00\xc3\xbc".to_vec())
+        );
+        assert!(parse_quoted("abc").is_err());
+        assert!(parse_quoted(r#""a"b""#).is_err());
+        assert!(parse_quoted(r#""\x4""#).is_err());
+        assert!(parse_quoted(r#""\q""#).is_err());
+    }
+
+    #[test]
+    fn it_should_complete_commands() {
+        assert_eq!(complete_command(":s"), vec![":save", ":set", ":state"]);
+        assert_eq!(
+            complete_command(":"),
+            COMMANDS
+                .iter()
+                .map(|c| format!(":{}", c))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(complete_command(":goto 1"), Vec::<String>::new());
+        assert_eq!(complete_command("+s"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn it_should_reject_invalid_commands() {
+        let mut repl = Repl::new(&Options::default());
+
+        assert_eq!(
+            feed(&mut repl, ":goto 70000"),
+            Err(ReplError::Command("Invalid position `70000`".to_string()))
+        );
+        assert_eq!(
+            feed(&mut repl, ":undo"),
+            Err(ReplError::Command("Nothing to undo".to_string()))
+        );
+        assert_eq!(
+            feed(&mut repl, ":jump"),
+            Err(ReplError::Command(
+                "Unknown command `:jump`, :help lists all commands".to_string()
+            ))
+        );
+    }
+}
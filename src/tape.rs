@@ -0,0 +1,343 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fmt;
+
+use vm::NUMBER_OF_CELLS;
+
+/// The value of a cell, which wraps around at the width of the type
+pub trait CellValue: Copy + PartialEq + fmt::Debug + fmt::Display + fmt::LowerHex {
+    const ZERO: Self;
+    /// The largest value, which is -1 for programs that treat cells as signed
+    const MAX: Self;
+
+    /// The value modulo 2 to the power of the width of the cell
+    fn wrapping_from(value: u32) -> Self;
+
+    fn to_u32(self) -> u32;
+
+    fn wrapping_add(self, other: Self) -> Self;
+
+    fn wrapping_sub(self, other: Self) -> Self;
+
+    fn wrapping_mul(self, other: Self) -> Self;
+
+    /// The cells as bytes if every cell is one byte, which makes scans faster
+    fn as_bytes(cells: &[Self]) -> Option<&[u8]>;
+}
+
+macro_rules! cell_value {
+    ($type:ty, $cells:ident => $as_bytes:expr) => {
+        impl CellValue for $type {
+            const ZERO: Self = 0;
+            const MAX: Self = <$type>::MAX;
+
+            #[inline]
+            fn wrapping_from(value: u32) -> Self {
+                value as $type
+            }
+
+            #[inline]
+            fn to_u32(self) -> u32 {
+                u32::from(self)
+            }
+
+            #[inline]
+            fn wrapping_add(self, other: Self) -> Self {
+                <$type>::wrapping_add(self, other)
+            }
+
+            #[inline]
+            fn wrapping_sub(self, other: Self) -> Self {
+                <$type>::wrapping_sub(self, other)
+            }
+
+            #[inline]
+            fn wrapping_mul(self, other: Self) -> Self {
+                <$type>::wrapping_mul(self, other)
+            }
+
+            fn as_bytes($cells: &[Self]) -> Option<&[u8]> {
+                $as_bytes
+            }
+        }
+    };
+}
+
+cell_value!(u8, cells => Some(cells));
+cell_value!(u16, _cells => None);
+cell_value!(u32, _cells => None);
+
+/// Storage for the cells of the tape. The data pointer wraps around at both ends of the tape.
+// Tapes always have at least one cell
+#[allow(clippy::len_without_is_empty)]
+pub trait Tape: Clone {
+    type Cell: CellValue;
+
+    /// An empty tape with `len` cells, if the storage supports that many
+    fn with_len(len: usize) -> Option<Self>;
+
+    fn len(&self) -> usize;
+
+    fn get(&self, i: usize) -> Self::Cell;
+
+    fn get_mut(&mut self, i: usize) -> &mut Self::Cell;
+
+    /// The cells as one slice if they are stored in one piece, which makes scans faster
+    fn as_slice(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// Every cell that is not zero, ordered by position
+    fn nonzero(&self) -> Vec<(usize, Self::Cell)> {
+        (0..self.len())
+            .map(|i| (i, self.get(i)))
+            .filter(|(_, v)| *v != Self::Cell::ZERO)
+            .collect()
+    }
+
+    /// The position `offset` cells away from `pos`
+    #[inline]
+    fn offset(&self, pos: usize, offset: isize) -> usize {
+        let len = self.len() as isize;
+        let moved = (pos as isize).wrapping_add(offset);
+
+        if 0 <= moved && moved < len {
+            moved as usize
+        } else {
+            (pos as isize + offset % len).rem_euclid(len) as usize
+        }
+    }
+}
+
+/// The default tape, which always has `NUMBER_OF_CELLS` cells
+impl Tape for [u8; NUMBER_OF_CELLS] {
+    type Cell = u8;
+
+    fn with_len(len: usize) -> Option<Self> {
+        if len == NUMBER_OF_CELLS {
+            Some([0; NUMBER_OF_CELLS])
+        } else {
+            None
+        }
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        NUMBER_OF_CELLS
+    }
+
+    #[inline]
+    fn get(&self, i: usize) -> u8 {
+        self[i]
+    }
+
+    #[inline]
+    fn get_mut(&mut self, i: usize) -> &mut u8 {
+        &mut self[i]
+    }
+
+    fn as_slice(&self) -> Option<&[u8]> {
+        Some(self)
+    }
+
+    #[inline]
+    fn offset(&self, pos: usize, offset: isize) -> usize {
+        (pos as u16).wrapping_add(offset as u16) as usize
+    }
+}
+
+/// A tape of any length in one allocation on the heap
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeapTape<T = u8> {
+    cells: Vec<T>,
+}
+
+impl<T: CellValue> Tape for HeapTape<T> {
+    type Cell = T;
+
+    fn with_len(len: usize) -> Option<Self> {
+        if len == 0 || len > isize::MAX as usize / 2 {
+            return None;
+        }
+
+        // Reserve first so that a tape that does not fit into memory is rejected instead of aborting
+        let mut cells = Vec::new();
+        cells.try_reserve_exact(len).ok()?;
+        cells.resize(len, T::ZERO);
+
+        Some(HeapTape { cells })
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    #[inline]
+    fn get(&self, i: usize) -> T {
+        self.cells[i]
+    }
+
+    #[inline]
+    fn get_mut(&mut self, i: usize) -> &mut T {
+        &mut self.cells[i]
+    }
+
+    fn as_slice(&self) -> Option<&[u8]> {
+        T::as_bytes(&self.cells)
+    }
+}
+
+const PAGE_SIZE: usize = 4096;
+
+/// A tape that only allocates the pages that have been written to, for tapes that are too large to
+/// allocate at once
+#[derive(Debug, Clone)]
+pub struct SparseTape<T = u8> {
+    len: usize,
+    pages: Vec<[T; PAGE_SIZE]>,
+    /// Where in `pages` each allocated page is
+    slots: HashMap<usize, usize>,
+    /// The last page looked up and its slot, since programs mostly stay on one page for a while
+    last: Cell<(usize, usize)>,
+}
+
+impl<T> SparseTape<T> {
+    #[inline]
+    fn slot(&self, page: usize) -> Option<usize> {
+        let (last, slot) = self.last.get();
+        if last == page {
+            Some(slot)
+        } else {
+            self.look_up(page)
+        }
+    }
+
+    #[inline(never)]
+    fn look_up(&self, page: usize) -> Option<usize> {
+        let slot = *self.slots.get(&page)?;
+        self.last.set((page, slot));
+        Some(slot)
+    }
+}
+
+impl<T: CellValue> PartialEq for SparseTape<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.nonzero() == other.nonzero()
+    }
+}
+
+impl<T: CellValue> Tape for SparseTape<T> {
+    type Cell = T;
+
+    fn with_len(len: usize) -> Option<Self> {
+        if len == 0 || len > isize::MAX as usize / 2 {
+            return None;
+        }
+
+        Some(SparseTape {
+            len,
+            pages: vec![],
+            slots: HashMap::new(),
+            // No page has this index, since the length is limited
+            last: Cell::new((usize::MAX, 0)),
+        })
+    }
+
+    #[inline]
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    fn get(&self, i: usize) -> T {
+        self.slot(i / PAGE_SIZE)
+            .map_or(T::ZERO, |slot| self.pages[slot][i % PAGE_SIZE])
+    }
+
+    #[inline]
+    fn get_mut(&mut self, i: usize) -> &mut T {
+        assert!(i < self.len, "cell {} is outside of the tape", i);
+
+        let page = i / PAGE_SIZE;
+        let slot = match self.slot(page) {
+            Some(slot) => slot,
+            None => {
+                self.pages.push([T::ZERO; PAGE_SIZE]);
+                self.slots.insert(page, self.pages.len() - 1);
+                self.pages.len() - 1
+            }
+        };
+        &mut self.pages[slot][i % PAGE_SIZE]
+    }
+
+    fn nonzero(&self) -> Vec<(usize, T)> {
+        let mut slots: Vec<_> = self.slots.iter().collect();
+        slots.sort_by_key(|(index, _)| **index);
+
+        slots
+            .into_iter()
+            .flat_map(|(index, slot)| {
+                self.pages[*slot]
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, v)| **v != T::ZERO)
+                    .map(move |(i, v)| (index * PAGE_SIZE + i, *v))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_tape<T: Tape>(len: usize) {
+        let mut tape = T::with_len(len).unwrap();
+        let v = T::Cell::wrapping_from;
+
+        assert_eq!(tape.len(), len);
+        assert_eq!(tape.offset(0, -1), len - 1);
+        assert_eq!(tape.offset(len - 2, 5), 3);
+        assert_eq!(tape.offset(7, -(len as isize) - 2), 5);
+
+        *tape.get_mut(len - 1) = v(3);
+        *tape.get_mut(1) = v(200);
+        *tape.get_mut(2) = v(1);
+        *tape.get_mut(2) = tape.get(2).wrapping_sub(v(1));
+
+        assert_eq!(tape.get(1), v(200));
+        assert_eq!(tape.get(len - 1), v(3));
+        assert_eq!(tape.nonzero(), vec![(1, v(200)), (len - 1, v(3))]);
+    }
+
+    #[test]
+    fn it_should_store_cells_in_every_tape() {
+        check_tape::<[u8; NUMBER_OF_CELLS]>(NUMBER_OF_CELLS);
+        check_tape::<HeapTape>(10);
+        check_tape::<HeapTape>(NUMBER_OF_CELLS * 4);
+        check_tape::<SparseTape>(10);
+        check_tape::<SparseTape>(1 << 40);
+        check_tape::<HeapTape<u16>>(10);
+        check_tape::<SparseTape<u32>>(1 << 40);
+    }
+
+    #[test]
+    fn it_should_wrap_cells_around_at_their_width() {
+        assert_eq!(u8::wrapping_from(300), 44);
+        assert_eq!(u16::wrapping_from(300), 300);
+        assert_eq!(u16::wrapping_from(70_000), 4_464);
+        assert_eq!(CellValue::wrapping_sub(0u32, 1), u32::MAX);
+
+        assert_eq!(u8::as_bytes(&[1, 2]), Some(&[1u8, 2][..]));
+        assert_eq!(u16::as_bytes(&[1, 2]), None);
+    }
+
+    #[test]
+    fn it_should_reject_unsupported_lengths() {
+        assert!(<[u8; NUMBER_OF_CELLS]>::with_len(100).is_none());
+        assert!(<HeapTape>::with_len(0).is_none());
+        assert!(<HeapTape>::with_len(1 << 60).is_none());
+        assert!(<SparseTape>::with_len(usize::MAX).is_none());
+    }
+}
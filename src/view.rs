@@ -0,0 +1,225 @@
+use std::mem;
+
+use tape::{CellValue, Tape};
+use vm::State;
+
+/// How the values of cells are shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum CellFormat {
+    Decimal,
+    Hex,
+    /// Printable characters as they are, everything else escaped like `\n` or `\x07`
+    Ascii,
+}
+
+/// Renders a part of the tape as a table of positions, values and a marker below the current cell
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TapeView {
+    /// The number of cells to show
+    pub window: usize,
+    /// The cell in the middle of the window, the current cell if `None`
+    pub center: Option<usize>,
+    pub format: CellFormat,
+    /// Whether to show the values of cells that are not zero in bold
+    pub highlight: bool,
+    /// Whether to show every cell of the tape that is not zero, and the current cell, instead of the window
+    pub compact: bool,
+    /// The number of cells in one row of the table
+    pub columns: usize,
+}
+
+impl Default for TapeView {
+    fn default() -> Self {
+        TapeView {
+            window: 25,
+            center: None,
+            format: CellFormat::Decimal,
+            highlight: false,
+            compact: false,
+            columns: 25,
+        }
+    }
+}
+
+const CELL_WIDTH: usize = 6;
+
+impl TapeView {
+    /// A view of the cells from `from` to `to`, both included
+    pub fn range(from: usize, to: usize) -> TapeView {
+        let window = to.saturating_sub(from) + 1;
+
+        TapeView {
+            window,
+            center: Some(from + window / 2),
+            ..TapeView::default()
+        }
+    }
+
+    /// The positions of the cells to show, wrapping around the ends of the tape
+    fn positions<C: Tape>(&self, s: &State<C>) -> Vec<usize> {
+        if self.compact {
+            let mut positions: Vec<_> = s.cells.nonzero().into_iter().map(|(i, _)| i).collect();
+            if let Err(i) = positions.binary_search(&s.pos) {
+                positions.insert(i, s.pos);
+            }
+            return positions;
+        }
+
+        let len = s.cells.len();
+        let window = self.window.clamp(1, len);
+        let center = self.center.unwrap_or(s.pos) % len;
+        let start = center + len - window / 2;
+
+        (0..window).map(|i| (start + i) % len).collect()
+    }
+
+    fn value<V: CellValue>(&self, v: V, width: usize) -> String {
+        let value = match self.format {
+            CellFormat::Decimal => v.to_string(),
+            CellFormat::Hex => format!("{:01$x}", v, mem::size_of::<V>() * 2),
+            CellFormat::Ascii => match v.to_u32() {
+                0x0a => "\\n".to_string(),
+                0x0d => "\\r".to_string(),
+                0x09 => "\\t".to_string(),
+                0 => "\\0".to_string(),
+                c @ 0x20..=0x7e => (c as u8 as char).to_string(),
+                _ => format!("\\x{:02x}", v),
+            },
+        };
+        let value = format!("{:>width$}", value, width = width);
+
+        if self.highlight && v != V::ZERO {
+            format!("\x1b[1m{}\x1b[0m", value)
+        } else {
+            value
+        }
+    }
+
+    pub fn render<C: Tape>(&self, s: &State<C>) -> String {
+        let positions = self.positions(s);
+        let mut rows = vec![];
+        // The largest values of wider cells need more room
+        let width = CELL_WIDTH.max(C::Cell::MAX.to_string().len() + 1);
+
+        for chunk in positions.chunks(self.columns.max(1)) {
+            let row = |cell: &dyn Fn(usize) -> String| {
+                chunk
+                    .iter()
+                    .fold("|".to_string(), |row, i| row + &cell(*i) + "|")
+            };
+
+            rows.push(row(&|i| format!("{:width$}", i, width = width)));
+            rows.push(row(&|i| self.value(s.cells.get(i), width)));
+            rows.push(row(&|i| {
+                let marker = if i == s.pos { "*" } else { " " };
+                marker.repeat(width)
+            }));
+        }
+        rows.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> State {
+        let mut s = State {
+            pos: 2,
+            ..State::default()
+        };
+        s.cells[1] = b'A';
+        s.cells[2] = 10;
+        s.cells[500] = 255;
+        s
+    }
+
+    #[test]
+    fn it_should_render_a_window_around_the_pointer() {
+        let view = TapeView {
+            window: 5,
+            ..TapeView::default()
+        };
+
+        assert_eq!(
+            view.render(&state()),
+            "|     0|     1|     2|     3|     4|\n\
+             |     0|    65|    10|     0|     0|\n\
+             |      |      |******|      |      |"
+        );
+    }
+
+    #[test]
+    fn it_should_wrap_around_the_ends_of_the_tape() {
+        let view = TapeView {
+            window: 4,
+            center: Some(0),
+            ..TapeView::default()
+        };
+
+        assert_eq!(
+            view.render(&state()).lines().next(),
+            Some("| 65534| 65535|     0|     1|")
+        );
+    }
+
+    #[test]
+    fn it_should_render_values_in_every_format() {
+        let view = TapeView::range(0, 2);
+        let hex = TapeView {
+            format: CellFormat::Hex,
+            ..view.clone()
+        };
+        let ascii = TapeView {
+            format: CellFormat::Ascii,
+            ..view.clone()
+        };
+
+        assert_eq!(
+            hex.render(&state()).lines().nth(1),
+            Some("|    00|    41|    0a|")
+        );
+        assert_eq!(
+            ascii.render(&state()).lines().nth(1),
+            Some("|    \\0|     A|    \\n|")
+        );
+    }
+
+    #[test]
+    fn it_should_render_ranges_in_rows() {
+        let view = TapeView {
+            columns: 2,
+            ..TapeView::range(499, 501)
+        };
+
+        assert_eq!(
+            view.render(&state()),
+            "|   499|   500|\n\
+             |     0|   255|\n\
+             |      |      |\n\
+             |   501|\n\
+             |     0|\n\
+             |      |"
+        );
+    }
+
+    #[test]
+    fn it_should_only_render_cells_that_are_not_zero_in_compact_mode() {
+        let mut s = state();
+        s.pos = 3;
+        let view = TapeView {
+            compact: true,
+            highlight: true,
+            ..TapeView::default()
+        };
+
+        assert_eq!(
+            view.render(&s),
+            "|     1|     2|     3|   500|\n\
+             |\x1b[1m    65\x1b[0m|\x1b[1m    10\x1b[0m|     0|\x1b[1m   255\x1b[0m|\n\
+             |      |      |******|      |"
+        );
+    }
+}
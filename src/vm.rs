@@ -1,145 +1,682 @@
+use memchr::{memchr, memrchr};
 use std::default::Default;
+use std::error::Error;
 use std::fmt;
-use std::io::{Read, Write};
+use std::io::{ErrorKind, Read, Write};
 
-const NUMBER_OF_CELLS: usize = u16::max_value() as usize + 1;
+use parser::Location;
+use tape::{CellValue, Tape};
+use view::TapeView;
 
+pub const NUMBER_OF_CELLS: usize = u16::MAX as usize + 1;
+
+/// Loops running more iterations than this are checked for repeating states
+const CYCLE_CHECK_ITERATIONS: usize = 1 << 16;
+
+/// The data pointer and the tape, stored in a fixed array unless another `Tape` is chosen
 #[derive(Clone)]
-pub struct State {
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(into = "SparseState", try_from = "SparseState", bound = "C: Tape")
+)]
+pub struct State<C = [u8; NUMBER_OF_CELLS]> {
     pub pos: usize,
-    pub cells: [u8; NUMBER_OF_CELLS as usize],
+    pub cells: C,
+    pub eof: EofPolicy,
 }
 
+/// A state with the tape stored inline, `State::<HeapTape>::with_len` keeps the tape off the stack
 impl Default for State {
     fn default() -> Self {
         State {
             pos: 0,
-            cells: [0; NUMBER_OF_CELLS as usize],
+            cells: [0; NUMBER_OF_CELLS],
+            eof: EofPolicy::default(),
+        }
+    }
+}
+
+impl<C: Tape> State<C> {
+    /// An empty tape with `len` cells, if the tape storage supports that many
+    pub fn with_len(len: usize) -> Option<Self> {
+        Some(State {
+            pos: 0,
+            cells: C::with_len(len)?,
+            eof: EofPolicy::default(),
+        })
+    }
+}
+
+/// What reading input does when there is no more input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum EofPolicy {
+    /// Stop with a `RuntimeError::ReadError`
+    #[default]
+    Error,
+    /// Set the cell to 0
+    Zero,
+    /// Set the cell to its largest value, 255 for 8-bit cells
+    MinusOne,
+    /// Leave the cell as it is
+    Unchanged,
+}
+
+/// The serialized form of a `State`, which only contains the cells that are not zero
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct SparseState {
+    pos: usize,
+    #[serde(default = "default_len", skip_serializing_if = "is_default_len")]
+    len: usize,
+    cells: Vec<(usize, u32)>,
+    #[serde(default)]
+    eof: EofPolicy,
+}
+
+#[cfg(feature = "serde")]
+fn default_len() -> usize {
+    NUMBER_OF_CELLS
+}
+
+#[cfg(feature = "serde")]
+fn is_default_len(len: &usize) -> bool {
+    *len == NUMBER_OF_CELLS
+}
+
+#[cfg(feature = "serde")]
+impl<C: Tape> From<State<C>> for SparseState {
+    fn from(s: State<C>) -> Self {
+        SparseState {
+            pos: s.pos,
+            len: s.cells.len(),
+            cells: s
+                .cells
+                .nonzero()
+                .into_iter()
+                .map(|(i, v)| (i, v.to_u32()))
+                .collect(),
+            eof: s.eof,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<C: Tape> ::std::convert::TryFrom<SparseState> for State<C> {
+    type Error = String;
+
+    fn try_from(sparse: SparseState) -> Result<Self, Self::Error> {
+        let mut s = State::<C>::with_len(sparse.len)
+            .ok_or_else(|| format!("A tape of {} cells is not supported", sparse.len))?;
+
+        if sparse.pos >= sparse.len {
+            return Err(format!(
+                "Data pointer {} is outside of the tape",
+                sparse.pos
+            ));
         }
+        s.pos = sparse.pos;
+        s.eof = sparse.eof;
+
+        for (i, v) in sparse.cells {
+            if i >= sparse.len {
+                return Err(format!("Cell {} is outside of the tape", i));
+            }
+            let value = C::Cell::wrapping_from(v);
+            if value.to_u32() != v {
+                return Err(format!(
+                    "The value {} of cell {} does not fit into a cell",
+                    v, i
+                ));
+            }
+            *s.cells.get_mut(i) = value;
+        }
+        Ok(s)
     }
 }
 
 #[derive(Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum RuntimeError {
     WriteError(String),
     ReadError(String),
+    /// A loop that never terminates, at its location in the executed code
+    InfiniteLoop(Location),
 }
 
-impl fmt::Display for State {
-    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        let cell_count = 25;
-        let cells_to_show: Vec<usize> = (0..25)
-            .map(|i| {
-                let offset = cell_count / 2;
-                let pos: i64 = self.pos as i64 + i - offset;
-
-                if pos < 0 {
-                    (NUMBER_OF_CELLS as i64 + pos) as usize
-                } else if pos >= NUMBER_OF_CELLS as i64 {
-                    (pos - NUMBER_OF_CELLS as i64) as usize
-                } else {
-                    pos as usize
-                }
-            })
-            .collect();
+impl RuntimeError {
+    pub fn location(&self) -> Option<Location> {
+        match *self {
+            RuntimeError::InfiniteLoop(location) => Some(location),
+            _ => None,
+        }
+    }
 
-        f.write_str("Brainfuck state:\n")?;
-        f.write_str("|")?;
-        for cell in &cells_to_show {
-            f.write_str(&format!("{:6}", cell))?;
-            f.write_str("|")?;
-        }
-        f.write_str("\n|")?;
-        for cell in &cells_to_show {
-            f.write_str(&format!("{:6}", self.cells[*cell]))?;
-            f.write_str("|")?;
-        }
-        f.write_str("\n|")?;
-        for cell in &cells_to_show {
-            if *cell == self.pos {
-                f.write_str("******|")?;
-            } else {
-                f.write_str("      |")?;
+    /// Makes the location of an error in the `index`th node of a block relative to the block
+    #[cold]
+    fn within(self, block: &[Node], index: usize) -> RuntimeError {
+        match self {
+            RuntimeError::InfiniteLoop(location) => {
+                RuntimeError::InfiniteLoop(Location::default().of(block, index).join(location))
+            }
+            e => e,
+        }
+    }
+
+    /// Makes the location of an error in the body of a loop relative to the loop
+    #[cold]
+    fn inside_loop(self) -> RuntimeError {
+        match self {
+            RuntimeError::InfiniteLoop(location) => {
+                RuntimeError::InfiniteLoop(Location::default().next().join(location))
+            }
+            e => e,
+        }
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            RuntimeError::WriteError(e) => write!(f, "could not write output: {}", e),
+            RuntimeError::ReadError(e) => write!(f, "could not read input: {}", e),
+            RuntimeError::InfiniteLoop(l) => {
+                write!(f, "{}:{}: this loop never terminates", l.line, l.column)
             }
         }
+    }
+}
+
+impl Error for RuntimeError {}
 
-        Ok(())
+impl<C: Tape> fmt::Display for State<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        f.write_str("Brainfuck state:\n")?;
+        f.write_str(&TapeView::default().render(self))
     }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Node {
     Shift(isize),
     // value, (offset_to), offset, move_pointer
     Inc(u8, isize, bool),
     Dec(u8, isize, bool),
     Mul(i16, isize, isize, bool),
+    // value, offset_to, offset of the first factor, offset of the second factor
+    Product(i16, isize, isize, isize),
     Assign(u8, isize, bool),
     Scan(isize),
     Out(isize, bool),
     In(isize, bool),
     Conditional(Vec<Node>),
+    // boxed, so that loops with an offset and a stride do not make every other node larger
+    Loop(Box<Loop>),
     Comment(char),
 }
 
-pub fn run_block<R: Read, W: Write>(
+/// A loop with its condition at an offset from the data pointer
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Loop {
+    pub offset: isize,
+    /// The data pointer movement per iteration
+    pub stride: isize,
+    pub body: Vec<Node>,
+}
+
+/// The kind of a node, without any of its values
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum InstructionKind {
+    Shift,
+    Inc,
+    Dec,
+    Mul,
+    Product,
+    Assign,
+    Scan,
+    Out,
+    In,
+    Conditional,
+    Loop,
+    Comment,
+}
+
+impl InstructionKind {
+    pub const ALL: [InstructionKind; 12] = [
+        InstructionKind::Shift,
+        InstructionKind::Inc,
+        InstructionKind::Dec,
+        InstructionKind::Mul,
+        InstructionKind::Product,
+        InstructionKind::Assign,
+        InstructionKind::Scan,
+        InstructionKind::Out,
+        InstructionKind::In,
+        InstructionKind::Conditional,
+        InstructionKind::Loop,
+        InstructionKind::Comment,
+    ];
+
+    /// A stable name, used as a key in serialized output
+    pub fn name(self) -> &'static str {
+        match self {
+            InstructionKind::Shift => "shift",
+            InstructionKind::Inc => "inc",
+            InstructionKind::Dec => "dec",
+            InstructionKind::Mul => "mul",
+            InstructionKind::Product => "product",
+            InstructionKind::Assign => "assign",
+            InstructionKind::Scan => "scan",
+            InstructionKind::Out => "out",
+            InstructionKind::In => "in",
+            InstructionKind::Conditional => "conditional",
+            InstructionKind::Loop => "loop",
+            InstructionKind::Comment => "comment",
+        }
+    }
+}
+
+impl Node {
+    /// A loop with its condition at `offset` from the data pointer
+    pub fn new_loop(offset: isize, stride: isize, body: Vec<Node>) -> Node {
+        Node::Loop(Box::new(Loop {
+            offset,
+            stride,
+            body,
+        }))
+    }
+
+    /// The offset of the condition and the body, if this is a `Conditional` or a `Loop`
+    pub fn as_loop(&self) -> Option<(isize, &[Node])> {
+        match *self {
+            Node::Conditional(ref body) => Some((0, body)),
+            Node::Loop(ref l) => Some((l.offset, &l.body)),
+            _ => None,
+        }
+    }
+
+    pub fn kind(&self) -> InstructionKind {
+        match self {
+            Node::Shift(_) => InstructionKind::Shift,
+            Node::Inc(_, _, _) => InstructionKind::Inc,
+            Node::Dec(_, _, _) => InstructionKind::Dec,
+            Node::Mul(_, _, _, _) => InstructionKind::Mul,
+            Node::Product(_, _, _, _) => InstructionKind::Product,
+            Node::Assign(_, _, _) => InstructionKind::Assign,
+            Node::Scan(_) => InstructionKind::Scan,
+            Node::Out(_, _) => InstructionKind::Out,
+            Node::In(_, _) => InstructionKind::In,
+            Node::Conditional(_) => InstructionKind::Conditional,
+            Node::Loop(_) => InstructionKind::Loop,
+            Node::Comment(_) => InstructionKind::Comment,
+        }
+    }
+}
+
+/// Gets called with every node before the VM executes it, `()` does nothing
+pub trait Tracer {
+    fn trace(&mut self, node: &Node);
+}
+
+impl Tracer for () {
+    #[inline]
+    fn trace(&mut self, _node: &Node) {}
+}
+
+pub fn run_block<R: Read, W: Write, C: Tape>(
+    stdin: &mut R,
+    stdout: &mut W,
+    block: &[Node],
+    s: &mut State<C>,
+) -> Result<(), RuntimeError> {
+    run_block_traced(stdin, stdout, block, s, &mut ())
+}
+
+// Inlining this into the loops running it makes every iteration slower
+#[inline(never)]
+pub fn run_block_traced<R: Read, W: Write, T: Tracer, C: Tape>(
     stdin: &mut R,
     stdout: &mut W,
     block: &[Node],
-    s: &mut State,
+    s: &mut State<C>,
+    tracer: &mut T,
 ) -> Result<(), RuntimeError> {
-    for node in block {
-        node.execute(stdin, stdout, s)?;
+    let mut nodes = block.iter();
+    nodes
+        .try_for_each(|node| {
+            tracer.trace(node);
+            node.execute_traced(stdin, stdout, s, tracer)
+        })
+        // The failed node is the last one taken from the iterator
+        .map_err(|e| e.within(block, block.len() - nodes.len() - 1))
+}
+
+/// Whether the loop condition at `offset` from the data pointer holds
+#[inline]
+fn is_nonzero<C: Tape>(s: &State<C>, offset: isize) -> bool {
+    if offset == 0 {
+        s.cells.get(s.pos) != C::Cell::ZERO
+    } else {
+        s.cells.get(s.cells.offset(s.pos, offset)) != C::Cell::ZERO
+    }
+}
+
+fn run_loop<R: Read, W: Write, T: Tracer, C: Tape>(
+    stdin: &mut R,
+    stdout: &mut W,
+    offset: isize,
+    body: &[Node],
+    s: &mut State<C>,
+    tracer: &mut T,
+) -> Result<(), RuntimeError> {
+    // Most loops end long before it is worth looking for cycles, so they only count their iterations
+    let mut remaining = CYCLE_CHECK_ITERATIONS;
+    while is_nonzero(s, offset) {
+        if remaining == 0 {
+            return run_long_loop(stdin, stdout, offset, body, s, tracer);
+        }
+        remaining -= 1;
+        if let Err(e) = run_block_traced(stdin, stdout, body, s, tracer) {
+            return Err(e.inside_loop());
+        }
+    }
+    Ok(())
+}
+
+/// Runs a loop that has already run `CYCLE_CHECK_ITERATIONS` times, and stops it when it never terminates
+#[inline(never)]
+fn run_long_loop<R: Read, W: Write, T: Tracer, C: Tape>(
+    stdin: &mut R,
+    stdout: &mut W,
+    offset: isize,
+    body: &[Node],
+    s: &mut State<C>,
+    tracer: &mut T,
+) -> Result<(), RuntimeError> {
+    let is_scan = body
+        .iter()
+        .all(|n| matches!(n, Node::Shift(_) | Node::Comment(_)));
+    if is_scan && is_nonzero(s, offset) && scan(&s.cells, s.pos, stride(body)).is_none() {
+        return Err(RuntimeError::InfiniteLoop(Location::default()));
+    }
+
+    let mut detector = footprint(body).map(|mut cells| {
+        cells.push(offset);
+        CycleDetector::new(cells, s)
+    });
+
+    while is_nonzero(s, offset) {
+        run_block_traced(stdin, stdout, body, s, tracer).map_err(RuntimeError::inside_loop)?;
+
+        if let Some(ref mut detector) = detector {
+            if detector.repeats(s) {
+                return Err(RuntimeError::InfiniteLoop(Location::default()));
+            }
+        }
     }
     Ok(())
 }
 
-fn offset_index(pos: usize, offset: isize) -> usize {
-    (pos as u16).wrapping_add(offset as u16) as usize
+/// The data pointer movement of a block that only consists of shifts
+fn stride(body: &[Node]) -> isize {
+    body.iter()
+        .map(|n| match n {
+            Node::Shift(v) => *v,
+            _ => 0,
+        })
+        .sum()
+}
+
+/// The cells a loop body accesses relative to the data pointer, if it does not do any I/O and always
+/// ends up where it started
+fn footprint(body: &[Node]) -> Option<Vec<isize>> {
+    let mut cells = vec![];
+    let mut current = 0;
+
+    for node in body {
+        match *node {
+            Node::Shift(v) => current += v,
+            Node::Inc(_, offset, move_pointer)
+            | Node::Dec(_, offset, move_pointer)
+            | Node::Assign(_, offset, move_pointer) => {
+                cells.push(current + offset);
+                if move_pointer {
+                    current += offset;
+                }
+            }
+            Node::Mul(_, into, offset, move_pointer) => {
+                cells.push(current + offset);
+                cells.push(current + offset + into);
+                if move_pointer {
+                    current += offset;
+                }
+            }
+            Node::Product(_, into, first, second) => {
+                cells.push(current + into);
+                cells.push(current + first);
+                cells.push(current + second);
+            }
+            Node::Conditional(ref body) => {
+                cells.push(current);
+                cells.extend(footprint(body)?.into_iter().map(|c| c + current));
+            }
+            Node::Loop(ref l) if l.stride == 0 => {
+                cells.push(current + l.offset);
+                cells.extend(footprint(&l.body)?.into_iter().map(|c| c + current));
+            }
+            Node::Comment(_) => {}
+            Node::Loop(_) | Node::Scan(_) | Node::In(_, _) | Node::Out(_, _) => return None,
+        }
+    }
+
+    if current != 0 {
+        return None;
+    }
+
+    cells.sort();
+    cells.dedup();
+    Some(cells)
+}
+
+/// Detects a loop running into a state it has been in before with Brent's algorithm
+struct CycleDetector<V> {
+    cells: Vec<isize>,
+    saved: (usize, Vec<V>),
+    power: usize,
+    length: usize,
+}
+
+impl<V: CellValue> CycleDetector<V> {
+    fn new<C: Tape<Cell = V>>(cells: Vec<isize>, s: &State<C>) -> Self {
+        let saved = Self::snapshot(&cells, s);
+
+        CycleDetector {
+            cells,
+            saved,
+            power: 1,
+            length: 0,
+        }
+    }
+
+    fn snapshot<C: Tape<Cell = V>>(cells: &[isize], s: &State<C>) -> (usize, Vec<V>) {
+        let values = cells
+            .iter()
+            .map(|c| s.cells.get(s.cells.offset(s.pos, *c)))
+            .collect();
+        (s.pos, values)
+    }
+
+    fn repeats<C: Tape<Cell = V>>(&mut self, s: &State<C>) -> bool {
+        let current = Self::snapshot(&self.cells, s);
+
+        if current == self.saved {
+            return true;
+        }
+
+        self.length += 1;
+        if self.length == self.power {
+            self.saved = current;
+            self.power *= 2;
+            self.length = 0;
+        }
+        false
+    }
+}
+
+/// How many cells a scan looks at one by one before it searches the rest of the tape
+const SHORT_SCAN: usize = 16;
+
+/// Finds the next zero cell starting at `pos` and moving by `interval`, wrapping around the tape
+#[inline]
+fn scan<C: Tape>(cells: &C, pos: usize, interval: isize) -> Option<usize> {
+    // Most scans stop after a few cells, which is faster to walk than to set up a search for
+    let mut pos = pos;
+    let mut remaining = SHORT_SCAN;
+    while cells.get(pos) != C::Cell::ZERO {
+        if remaining == 0 {
+            return scan_long(cells, pos, interval);
+        }
+        remaining -= 1;
+        pos = cells.offset(pos, interval);
+    }
+    Some(pos)
+}
+
+#[inline(never)]
+fn scan_long<C: Tape>(cells: &C, pos: usize, interval: isize) -> Option<usize> {
+    if let Some(slice) = cells.as_slice() {
+        return scan_slice(slice, pos, interval);
+    }
+
+    let len = cells.len();
+    let step = interval.unsigned_abs() % len;
+    let mut pos = pos;
+
+    if step == 0 {
+        return if cells.get(pos) == C::Cell::ZERO {
+            Some(pos)
+        } else {
+            None
+        };
+    }
+    for _ in 0..len / gcd(step, len) {
+        if cells.get(pos) == C::Cell::ZERO {
+            return Some(pos);
+        }
+        pos = cells.offset(pos, interval);
+    }
+    None
+}
+
+fn scan_slice(cells: &[u8], pos: usize, interval: isize) -> Option<usize> {
+    let len = cells.len();
+    let step = interval.unsigned_abs() % len;
+
+    if step == 0 {
+        return if cells[pos] == 0 { Some(pos) } else { None };
+    }
+    if step == 1 {
+        return if interval > 0 {
+            memchr(0, &cells[pos..])
+                .map(|i| pos + i)
+                .or_else(|| memchr(0, &cells[..pos]))
+        } else {
+            memrchr(0, &cells[..=pos])
+                .or_else(|| memrchr(0, &cells[pos + 1..]).map(|i| pos + 1 + i))
+        };
+    }
+
+    // Every position on the way is visited once before the scan ends up at the start again
+    let mut remaining = len / gcd(step, len);
+    let mut pos = pos;
+
+    while remaining > 0 {
+        if interval > 0 {
+            if let Some(i) = cells[pos..].iter().step_by(step).position(|c| *c == 0) {
+                return Some(pos + i * step);
+            }
+            let visited = (len - pos).div_ceil(step);
+            remaining = remaining.saturating_sub(visited);
+            pos = pos + visited * step - len;
+        } else {
+            if let Some(i) = cells[..=pos]
+                .iter()
+                .rev()
+                .step_by(step)
+                .position(|c| *c == 0)
+            {
+                return Some(pos - i * step);
+            }
+            let visited = pos / step + 1;
+            remaining = remaining.saturating_sub(visited);
+            pos = pos + len - visited * step;
+        }
+    }
+
+    None
+}
+
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
 }
 
 impl Node {
-    fn execute<R: Read, W: Write>(
+    #[cfg(test)]
+    fn execute<R: Read, W: Write, C: Tape>(
+        &self,
+        stdin: &mut R,
+        stdout: &mut W,
+        s: &mut State<C>,
+    ) -> Result<(), RuntimeError> {
+        self.execute_traced(stdin, stdout, s, &mut ())
+    }
+
+    fn execute_traced<R: Read, W: Write, T: Tracer, C: Tape>(
         &self,
         stdin: &mut R,
         stdout: &mut W,
-        s: &mut State,
+        s: &mut State<C>,
+        tracer: &mut T,
     ) -> Result<(), RuntimeError> {
         match *self {
-            Node::Conditional(ref body) => {
-                while s.cells[s.pos] != 0 {
-                    run_block(stdin, stdout, body, s)?;
-                }
-                Ok(())
-            }
+            Node::Conditional(ref body) => run_loop(stdin, stdout, 0, body, s, tracer),
+            Node::Loop(ref l) => run_loop(stdin, stdout, l.offset, &l.body, s, tracer),
             Node::Shift(i) => {
-                s.pos = offset_index(s.pos, i);
+                s.pos = s.cells.offset(s.pos, i);
                 Ok(())
             }
             Node::Inc(i, offset, move_pointer) => {
-                let pos = offset_index(s.pos, offset);
-                let v = &mut s.cells[pos];
-                *v = v.wrapping_add(i);
+                let pos = s.cells.offset(s.pos, offset);
+                let v = s.cells.get_mut(pos);
+                *v = v.wrapping_add(C::Cell::wrapping_from(u32::from(i)));
                 if move_pointer {
                     s.pos = pos;
                 }
                 Ok(())
             }
             Node::Dec(i, offset, move_pointer) => {
-                let pos = offset_index(s.pos, offset);
-                let v = &mut s.cells[pos];
-                *v = v.wrapping_sub(i);
+                let pos = s.cells.offset(s.pos, offset);
+                let v = s.cells.get_mut(pos);
+                *v = v.wrapping_sub(C::Cell::wrapping_from(u32::from(i)));
                 if move_pointer {
                     s.pos = pos;
                 }
                 Ok(())
             }
             Node::Mul(mul_value, into, offset, move_pointer) => {
-                let pos = offset_index(s.pos, offset);
-                let into_pos = offset_index(pos, into);
-                let v = s.cells[pos];
-                let into = &mut s.cells[into_pos];
-                let abs = mul_value.abs() as u8;
+                let pos = s.cells.offset(s.pos, offset);
+                let into_pos = s.cells.offset(pos, into);
+                let v = s.cells.get(pos);
+                let into = s.cells.get_mut(into_pos);
+                let abs = C::Cell::wrapping_from(u32::from(mul_value.unsigned_abs()));
 
                 if mul_value >= 0 {
                     *into = into.wrapping_add(v.wrapping_mul(abs));
@@ -151,27 +688,41 @@ impl Node {
                 }
                 Ok(())
             }
+            Node::Product(mul_value, into, first, second) => {
+                let v = s
+                    .cells
+                    .get(s.cells.offset(s.pos, first))
+                    .wrapping_mul(s.cells.get(s.cells.offset(s.pos, second)));
+                let into_pos = s.cells.offset(s.pos, into);
+                let into = s.cells.get_mut(into_pos);
+                let abs = C::Cell::wrapping_from(u32::from(mul_value.unsigned_abs()));
+
+                if mul_value >= 0 {
+                    *into = into.wrapping_add(v.wrapping_mul(abs));
+                } else {
+                    *into = into.wrapping_sub(v.wrapping_mul(abs));
+                }
+                Ok(())
+            }
             Node::Assign(i, offset, move_pointer) => {
-                let pos = offset_index(s.pos, offset);
-                s.cells[pos] = i;
+                let pos = s.cells.offset(s.pos, offset);
+                *s.cells.get_mut(pos) = C::Cell::wrapping_from(u32::from(i));
                 if move_pointer {
                     s.pos = pos;
                 }
                 Ok(())
             }
             Node::Scan(interval) => {
-                let mut pos = s.pos;
-                while s.cells[pos] != 0 {
-                    pos = offset_index(pos, interval);
-                }
-                s.pos = pos;
+                s.pos = scan(&s.cells, s.pos, interval)
+                    .ok_or_else(|| RuntimeError::InfiniteLoop(Location::default()))?;
                 Ok(())
             }
             Node::Out(offset, move_pointer) => {
-                let pos = offset_index(s.pos, offset);
+                let pos = s.cells.offset(s.pos, offset);
                 stdout
-                    .write(&[s.cells[pos]])
-                    .map_err(|e| RuntimeError::WriteError(format!("{:?}", e)))?;
+                    // Wider cells write their lowest byte
+                    .write_all(&[s.cells.get(pos).to_u32() as u8])
+                    .map_err(|e| RuntimeError::WriteError(e.to_string()))?;
 
                 if move_pointer {
                     s.pos = pos;
@@ -180,12 +731,21 @@ impl Node {
                 Ok(())
             }
             Node::In(offset, move_pointer) => {
-                let pos = offset_index(s.pos, offset);
-                let v = stdin
-                    .bytes()
-                    .next()
-                    .ok_or_else(|| RuntimeError::ReadError("No data from stdin".to_string()))?;
-                s.cells[pos] = v.map_err(|e| RuntimeError::ReadError(format!("{:?}", e)))?;
+                let pos = s.cells.offset(s.pos, offset);
+                let mut buf = [0; 1];
+                // Unlike read, read_exact tries again when reading is interrupted
+                match stdin.read_exact(&mut buf) {
+                    Ok(()) => *s.cells.get_mut(pos) = C::Cell::wrapping_from(u32::from(buf[0])),
+                    Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => match s.eof {
+                        EofPolicy::Error => {
+                            return Err(RuntimeError::ReadError("No data from stdin".to_string()))
+                        }
+                        EofPolicy::Zero => *s.cells.get_mut(pos) = C::Cell::ZERO,
+                        EofPolicy::MinusOne => *s.cells.get_mut(pos) = C::Cell::MAX,
+                        EofPolicy::Unchanged => {}
+                    },
+                    Err(e) => return Err(RuntimeError::ReadError(e.to_string())),
+                }
 
                 if move_pointer {
                     s.pos = pos;
@@ -201,6 +761,7 @@ impl Node {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tape::{HeapTape, SparseTape};
 
     #[test]
     fn it_should_increment_the_data_pointer() {
@@ -209,6 +770,7 @@ mod tests {
         let initial_state = State {
             pos: 0,
             cells: [0; NUMBER_OF_CELLS],
+            ..State::default()
         };
         let mut s = initial_state.clone();
 
@@ -227,6 +789,7 @@ mod tests {
         let initial_state = State {
             pos: NUMBER_OF_CELLS - 1,
             cells: [0; NUMBER_OF_CELLS],
+            ..State::default()
         };
         let mut s = initial_state.clone();
 
@@ -245,6 +808,7 @@ mod tests {
         let initial_state = State {
             pos: 1,
             cells: [0; NUMBER_OF_CELLS],
+            ..State::default()
         };
         let mut s = initial_state.clone();
 
@@ -263,6 +827,7 @@ mod tests {
         let initial_state = State {
             pos: 0,
             cells: [0; NUMBER_OF_CELLS],
+            ..State::default()
         };
         let mut s = initial_state.clone();
 
@@ -281,6 +846,7 @@ mod tests {
         let initial_state = State {
             pos: 0,
             cells: [0; NUMBER_OF_CELLS],
+            ..State::default()
         };
         let mut s = initial_state.clone();
 
@@ -300,6 +866,7 @@ mod tests {
         let initial_state = State {
             pos: 0,
             cells: [0; NUMBER_OF_CELLS],
+            ..State::default()
         };
         let mut s = initial_state.clone();
 
@@ -320,6 +887,7 @@ mod tests {
         let initial_state = State {
             pos: 0,
             cells: [0; NUMBER_OF_CELLS],
+            ..State::default()
         };
         let mut s = initial_state.clone();
 
@@ -340,6 +908,7 @@ mod tests {
         let initial_state = State {
             pos: NUMBER_OF_CELLS - 1,
             cells: [0; NUMBER_OF_CELLS],
+            ..State::default()
         };
         let mut s = initial_state.clone();
 
@@ -359,6 +928,7 @@ mod tests {
         let initial_state = State {
             pos: 0,
             cells: [0; NUMBER_OF_CELLS],
+            ..State::default()
         };
         let mut s = initial_state.clone();
 
@@ -371,7 +941,7 @@ mod tests {
             s.cells[0..(NUMBER_OF_CELLS - 2)],
             initial_state.cells[0..(NUMBER_OF_CELLS - 2)]
         );
-        assert_eq!(s.cells[(NUMBER_OF_CELLS - 1)], 1);
+        assert_eq!(s.cells[NUMBER_OF_CELLS - 1], 1);
     }
 
     #[test]
@@ -381,6 +951,7 @@ mod tests {
         let initial_state = State {
             pos: 1,
             cells: [2; NUMBER_OF_CELLS],
+            ..State::default()
         };
         let mut s = initial_state.clone();
 
@@ -405,6 +976,7 @@ mod tests {
         let initial_state = State {
             pos: 0,
             cells: [2; NUMBER_OF_CELLS],
+            ..State::default()
         };
         let mut s = initial_state.clone();
 
@@ -429,6 +1001,7 @@ mod tests {
         let initial_state = State {
             pos: 1,
             cells: [2; NUMBER_OF_CELLS],
+            ..State::default()
         };
         let mut s = initial_state.clone();
 
@@ -446,6 +1019,33 @@ mod tests {
         assert_eq!(s.cells[2], 8);
     }
 
+    #[test]
+    fn it_should_multiply_two_cells_into_another() {
+        let stdin = vec![];
+        let mut stdout = vec![];
+        let mut initial_state = State {
+            pos: 1,
+            cells: [2; NUMBER_OF_CELLS],
+            ..State::default()
+        };
+        initial_state.cells[0] = 3;
+        initial_state.cells[2] = 5;
+        let mut s = initial_state.clone();
+
+        Node::Product(2, 1, -1, 1)
+            .execute(&mut stdin.as_slice(), &mut stdout, &mut s)
+            .unwrap();
+        Node::Product(-1, 0, -1, -1)
+            .execute(&mut stdin.as_slice(), &mut stdout, &mut s)
+            .unwrap();
+
+        assert_eq!(s.pos, initial_state.pos);
+        assert_eq!(s.cells[3..], initial_state.cells[3..]);
+        assert_eq!(s.cells[0], 3);
+        assert_eq!(s.cells[1], 249);
+        assert_eq!(s.cells[2], 35);
+    }
+
     #[test]
     fn it_should_overflow_cells() {
         let stdin = vec![];
@@ -453,6 +1053,7 @@ mod tests {
         let initial_state = State {
             pos: 0,
             cells: [0; NUMBER_OF_CELLS],
+            ..State::default()
         };
         let mut s = initial_state.clone();
 
@@ -473,6 +1074,7 @@ mod tests {
         let initial_state = State {
             pos: 0,
             cells: [1; NUMBER_OF_CELLS],
+            ..State::default()
         };
         let mut s = initial_state.clone();
 
@@ -492,6 +1094,7 @@ mod tests {
         let initial_state = State {
             pos: 0,
             cells: [1; NUMBER_OF_CELLS],
+            ..State::default()
         };
         let mut s = initial_state.clone();
 
@@ -512,6 +1115,7 @@ mod tests {
         let initial_state = State {
             pos: 0,
             cells: [0; NUMBER_OF_CELLS],
+            ..State::default()
         };
         let mut s = initial_state.clone();
 
@@ -532,6 +1136,7 @@ mod tests {
         let initial_state = State {
             pos: NUMBER_OF_CELLS - 1,
             cells: [1; NUMBER_OF_CELLS],
+            ..State::default()
         };
         let mut s = initial_state.clone();
 
@@ -551,6 +1156,7 @@ mod tests {
         let initial_state = State {
             pos: 0,
             cells: [1; NUMBER_OF_CELLS],
+            ..State::default()
         };
         let mut s = initial_state.clone();
 
@@ -563,7 +1169,7 @@ mod tests {
             s.cells[0..(NUMBER_OF_CELLS - 2)],
             initial_state.cells[0..(NUMBER_OF_CELLS - 2)]
         );
-        assert_eq!(s.cells[(NUMBER_OF_CELLS - 1)], 0);
+        assert_eq!(s.cells[NUMBER_OF_CELLS - 1], 0);
     }
 
     #[test]
@@ -573,6 +1179,7 @@ mod tests {
         let initial_state = State {
             pos: 0,
             cells: [0; NUMBER_OF_CELLS],
+            ..State::default()
         };
         let mut s = initial_state.clone();
 
@@ -592,6 +1199,7 @@ mod tests {
         let initial_state = State {
             pos: 0,
             cells: [0; NUMBER_OF_CELLS],
+            ..State::default()
         };
         let mut s = initial_state.clone();
 
@@ -611,6 +1219,7 @@ mod tests {
         let initial_state = State {
             pos: 0,
             cells: [0; NUMBER_OF_CELLS],
+            ..State::default()
         };
         let mut s = initial_state.clone();
 
@@ -631,6 +1240,7 @@ mod tests {
         let initial_state = State {
             pos: 0,
             cells: [0; NUMBER_OF_CELLS],
+            ..State::default()
         };
         let mut s = initial_state.clone();
 
@@ -651,6 +1261,7 @@ mod tests {
         let initial_state = State {
             pos: NUMBER_OF_CELLS - 1,
             cells: [0; NUMBER_OF_CELLS],
+            ..State::default()
         };
         let mut s = initial_state.clone();
 
@@ -670,6 +1281,7 @@ mod tests {
         let initial_state = State {
             pos: 0,
             cells: [1; NUMBER_OF_CELLS],
+            ..State::default()
         };
         let mut s = initial_state.clone();
 
@@ -682,7 +1294,7 @@ mod tests {
             s.cells[0..(NUMBER_OF_CELLS - 2)],
             initial_state.cells[0..(NUMBER_OF_CELLS - 2)]
         );
-        assert_eq!(s.cells[(NUMBER_OF_CELLS - 1)], 5);
+        assert_eq!(s.cells[NUMBER_OF_CELLS - 1], 5);
     }
 
     #[test]
@@ -692,6 +1304,7 @@ mod tests {
         let initial_state = State {
             pos: 0,
             cells: [b'a'; NUMBER_OF_CELLS],
+            ..State::default()
         };
         let mut s = initial_state.clone();
 
@@ -711,6 +1324,7 @@ mod tests {
         let initial_state = State {
             pos: 0,
             cells: [b'a'; NUMBER_OF_CELLS],
+            ..State::default()
         };
         let mut s = initial_state.clone();
 
@@ -731,6 +1345,7 @@ mod tests {
         let initial_state = State {
             pos: 0,
             cells: [b'a'; NUMBER_OF_CELLS],
+            ..State::default()
         };
         let mut s = initial_state.clone();
 
@@ -751,6 +1366,7 @@ mod tests {
         let initial_state = State {
             pos: 0,
             cells: [b'a'; NUMBER_OF_CELLS],
+            ..State::default()
         };
         let mut s = initial_state.clone();
 
@@ -761,7 +1377,7 @@ mod tests {
         assert_eq!(s.pos, initial_state.pos);
         assert_eq!(s.cells[0..], initial_state.cells[0..]);
         assert_eq!(stdout.len(), 1);
-        assert_eq!(stdout.get(0), Some(&(b'a')));
+        assert_eq!(stdout.first(), Some(&(b'a')));
     }
 
     #[test]
@@ -771,6 +1387,7 @@ mod tests {
         let mut initial_state = State {
             pos: 0,
             cells: [b'a'; NUMBER_OF_CELLS],
+            ..State::default()
         };
         initial_state.cells[1] = b'b';
 
@@ -783,7 +1400,7 @@ mod tests {
         assert_eq!(s.pos, initial_state.pos);
         assert_eq!(s.cells[0..], initial_state.cells[0..]);
         assert_eq!(stdout.len(), 1);
-        assert_eq!(stdout.get(0), Some(&(b'b')));
+        assert_eq!(stdout.first(), Some(&(b'b')));
     }
 
     #[test]
@@ -793,6 +1410,7 @@ mod tests {
         let mut initial_state = State {
             pos: 0,
             cells: [b'a'; NUMBER_OF_CELLS],
+            ..State::default()
         };
         initial_state.cells[1] = b'b';
 
@@ -805,7 +1423,18 @@ mod tests {
         assert_eq!(s.pos, 1);
         assert_eq!(s.cells[0..], initial_state.cells[0..]);
         assert_eq!(stdout.len(), 1);
-        assert_eq!(stdout.get(0), Some(&(b'b')));
+        assert_eq!(stdout.first(), Some(&(b'b')));
+    }
+
+    #[test]
+    fn it_should_report_output_that_could_not_be_written() {
+        // Writing to an empty slice succeeds without writing anything
+        let mut stdout: &mut [u8] = &mut [];
+
+        let result =
+            Node::Out(0, false).execute(&mut [].as_ref(), &mut stdout, &mut State::default());
+
+        assert!(matches!(result, Err(RuntimeError::WriteError(_))));
     }
 
     #[test]
@@ -814,7 +1443,8 @@ mod tests {
         let mut stdout = vec![];
         let mut initial_state = State {
             pos: 21,
-            cells: [1 as u8; NUMBER_OF_CELLS],
+            cells: [1; NUMBER_OF_CELLS],
+            ..State::default()
         };
         initial_state.cells[10] = 0;
 
@@ -834,7 +1464,8 @@ mod tests {
         let mut stdout = vec![];
         let mut initial_state = State {
             pos: 10,
-            cells: [1 as u8; NUMBER_OF_CELLS],
+            cells: [1; NUMBER_OF_CELLS],
+            ..State::default()
         };
         initial_state.cells[9] = 0;
         initial_state.cells[8] = 0;
@@ -855,7 +1486,8 @@ mod tests {
         let mut stdout = vec![];
         let mut initial_state = State {
             pos: 0,
-            cells: [1 as u8; NUMBER_OF_CELLS],
+            cells: [1; NUMBER_OF_CELLS],
+            ..State::default()
         };
         initial_state.cells[9] = 0;
 
@@ -875,7 +1507,8 @@ mod tests {
         let mut stdout = vec![];
         let mut initial_state = State {
             pos: 0,
-            cells: [1 as u8; NUMBER_OF_CELLS],
+            cells: [1; NUMBER_OF_CELLS],
+            ..State::default()
         };
         initial_state.cells[1] = 0;
         initial_state.cells[2] = 0;
@@ -890,6 +1523,249 @@ mod tests {
         assert_eq!(s.cells[0..], initial_state.cells[0..]);
     }
 
+    #[test]
+    fn it_should_scan_across_the_tape_edges() {
+        let mut cells = [1; NUMBER_OF_CELLS];
+        cells[5] = 0;
+        cells[NUMBER_OF_CELLS - 6] = 0;
+
+        assert_eq!(scan(&cells, NUMBER_OF_CELLS - 3, 1), Some(5));
+        assert_eq!(scan(&cells, 3, -1), Some(NUMBER_OF_CELLS - 6));
+        assert_eq!(scan(&cells, NUMBER_OF_CELLS - 1, 3), Some(5));
+        assert_eq!(scan(&cells, 0, -2), Some(NUMBER_OF_CELLS - 6));
+        assert_eq!(scan(&cells, 0, 2), Some(NUMBER_OF_CELLS - 6));
+        assert_eq!(scan(&cells, 1, 2), Some(5));
+    }
+
+    #[test]
+    fn it_should_not_find_unreachable_zeros_when_scanning() {
+        let mut cells = [1; NUMBER_OF_CELLS];
+        cells[5] = 0;
+
+        assert_eq!(scan(&cells, 0, 2), None);
+        assert_eq!(scan(&cells, 0, -4), None);
+        assert_eq!(scan(&cells, 0, 0), None);
+        assert_eq!(scan(&[1; NUMBER_OF_CELLS], 0, 1), None);
+        assert_eq!(scan(&[1; NUMBER_OF_CELLS], 0, -1), None);
+    }
+
+    #[test]
+    fn it_should_scan_like_walking_the_tape() {
+        let mut cells = [0; NUMBER_OF_CELLS];
+        let mut seed: u32 = 42;
+        for cell in cells.iter_mut() {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            *cell = if (seed >> 16) % 4000 < 1 { 0 } else { 1 };
+        }
+
+        for interval in -9..10 {
+            for start in [0, 1, 17, 4096, NUMBER_OF_CELLS - 1].iter() {
+                let mut pos = *start;
+                let mut steps = 0;
+                while cells[pos] != 0 && steps < NUMBER_OF_CELLS {
+                    pos = cells.offset(pos, interval);
+                    steps += 1;
+                }
+                let expected = if cells[pos] == 0 { Some(pos) } else { None };
+
+                assert_eq!(scan(&cells, *start, interval), expected, "{}", interval);
+            }
+        }
+    }
+
+    #[test]
+    fn it_should_scan_tapes_without_a_slice() {
+        let mut cells = <SparseTape>::with_len(1 << 40).unwrap();
+        for i in 0..10_000 {
+            *cells.get_mut(i) = 1;
+        }
+        *cells.get_mut(9_000) = 0;
+
+        assert_eq!(scan(&cells, 0, 1), Some(9_000));
+        assert_eq!(scan(&cells, 0, 5_000), Some(10_000));
+        assert_eq!(scan(&cells, 100, -1), Some((1 << 40) - 1));
+
+        let mut cells = <SparseTape>::with_len(12).unwrap();
+        for i in 0..12 {
+            *cells.get_mut(i) = 1;
+        }
+        *cells.get_mut(7) = 0;
+
+        assert_eq!(scan(&cells, 1, 3), Some(7));
+        assert_eq!(scan(&cells, 9, -2), Some(7));
+        assert_eq!(scan(&cells, 0, 4), None);
+
+        // Wider cells that are a multiple of 256 are not zero
+        let mut cells = HeapTape::<u16>::with_len(12).unwrap();
+        *cells.get_mut(0) = 256;
+        *cells.get_mut(1) = 512;
+
+        assert_eq!(scan(&cells, 0, 1), Some(2));
+        assert_eq!(scan(&cells, 1, -1), Some(11));
+    }
+
+    #[test]
+    fn it_should_run_code_on_every_tape() {
+        fn run<C: Tape>(len: usize) -> (Vec<u8>, usize) {
+            let code = ::parser::parse_code(
+                &mut "++++++[>+++++++<-]>.<<<+++[>>>>>+<<<<<-]>>>>>.[<]<.>>,.".as_bytes(),
+            )
+            .unwrap();
+            let mut stdout = vec![];
+            let mut s = State::<C>::with_len(len).unwrap();
+
+            run_block(&mut b"x".as_ref(), &mut stdout, &code, &mut s).unwrap();
+            (stdout, s.pos)
+        }
+
+        let expected = (vec![42, 3, 42, b'x'], 3);
+        assert_eq!(run::<[u8; NUMBER_OF_CELLS]>(NUMBER_OF_CELLS), expected);
+        assert_eq!(run::<HeapTape>(NUMBER_OF_CELLS), expected);
+        assert_eq!(run::<SparseTape>(NUMBER_OF_CELLS), expected);
+        assert_eq!(run::<SparseTape>(1 << 40), expected);
+        // The data pointer wraps around the ends of a tape of any length
+        assert_eq!(run::<HeapTape>(7), expected);
+    }
+
+    #[test]
+    fn it_should_wrap_cells_around_at_their_width() {
+        fn run<C: Tape>() -> (Vec<u8>, Vec<(usize, C::Cell)>) {
+            // 8 * 8 * 4 = 256 in the first cell, and the second cell is decremented below zero
+            let code =
+                ::parser::parse_code(&mut "++++++++[>++++++++<-]>[<++++>-]-<.>.,".as_bytes())
+                    .unwrap();
+            let mut stdout = vec![];
+            let mut s = State::<C>::with_len(10).unwrap();
+            s.eof = EofPolicy::MinusOne;
+
+            run_block(&mut [].as_ref(), &mut stdout, &code, &mut s).unwrap();
+            (stdout, s.cells.nonzero())
+        }
+
+        assert_eq!(run::<HeapTape>(), (vec![0, 255], vec![(1, 255)]));
+        assert_eq!(
+            run::<HeapTape<u16>>(),
+            (vec![0, 255], vec![(0, 256), (1, u16::MAX)])
+        );
+        assert_eq!(
+            run::<SparseTape<u32>>(),
+            (vec![0, 255], vec![(0, 256), (1, u32::MAX)])
+        );
+    }
+
+    #[test]
+    fn it_should_detect_scans_that_never_find_a_zero() {
+        let stdin = vec![];
+        let mut stdout = vec![];
+        let mut s = State {
+            pos: 0,
+            cells: [1; NUMBER_OF_CELLS],
+            ..State::default()
+        };
+
+        let result = run_block(
+            &mut stdin.as_slice(),
+            &mut stdout,
+            &[Node::Inc(1, 0, false), Node::Scan(1)],
+            &mut s,
+        );
+
+        assert_eq!(
+            result,
+            Err(RuntimeError::InfiniteLoop(Location { line: 1, column: 2 }))
+        );
+    }
+
+    #[test]
+    fn it_should_detect_loops_repeating_their_state() {
+        let sources = ["+[]", "+[>+<]", "+[>>+<<[-]+]", "+>+[\n  +[]\n]"];
+        let locations = [(1, 2), (1, 2), (1, 2), (2, 4)];
+
+        for (source, location) in sources.iter().zip(locations.iter()) {
+            let stdin = vec![];
+            let mut stdout = vec![];
+            let mut s = State::default();
+            let code = ::parser::parse_code(&mut source.as_bytes()).unwrap();
+
+            let result = run_block(&mut stdin.as_slice(), &mut stdout, &code, &mut s);
+
+            assert_eq!(
+                result,
+                Err(RuntimeError::InfiniteLoop(Location {
+                    line: location.0,
+                    column: location.1
+                })),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn it_should_only_detect_states_that_repeat() {
+        let s = State::default();
+        let mut counter = State::default();
+        let mut detector = CycleDetector::new(vec![0, 1, 2], &s);
+
+        for i in 1..100_000u32 {
+            counter.cells[0] = i as u8;
+            counter.cells[1] = (i >> 8) as u8;
+            counter.cells[2] = (i >> 16) as u8;
+            assert!(!detector.repeats(&counter));
+        }
+        for i in 0..300_000u32 {
+            counter.cells[0] = (i % 300) as u8;
+            if detector.repeats(&counter) {
+                return;
+            }
+        }
+        panic!("The cycle was not detected");
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_scan_long_zero_free_runs() {
+        let mut initial_state = State {
+            pos: 0,
+            cells: [1; NUMBER_OF_CELLS],
+            ..State::default()
+        };
+        // Reachable with all the strides below
+        let zero = NUMBER_OF_CELLS - 16;
+        initial_state.cells[zero] = 0;
+
+        for interval in [1, 2, 3, 5].iter() {
+            let scan = [Node::Scan(*interval)];
+            let walk = [Node::Conditional(vec![Node::Shift(*interval)])];
+
+            for (name, code) in [("scan", &scan), ("walk", &walk)].iter() {
+                let start = ::std::time::Instant::now();
+                for _ in 0..100 {
+                    let mut s = initial_state.clone();
+                    run_block(&mut [].as_ref(), &mut vec![], *code, &mut s).unwrap();
+                    assert_eq!(s.pos, zero);
+                }
+                println!("{} {}: {:?}", name, interval, start.elapsed() / 100);
+            }
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_mandelbrot() {
+        let parsed =
+            ::parser::parse_code(&mut include_bytes!("../fuck/mandelbrot.fuck").as_ref()).unwrap();
+        let code =
+            ::optimizer::optimize_code(&parsed, &::optimizer::OptimizationOptions::default());
+
+        let start = ::std::time::Instant::now();
+        for _ in 0..3 {
+            let mut s = State::default();
+            run_block(&mut [].as_ref(), &mut ::std::io::sink(), &code, &mut s).unwrap();
+        }
+        println!("mandelbrot: {:?}", start.elapsed() / 3);
+    }
+
     #[test]
     fn it_should_run_nested_code_if_condition_is_true() {
         let stdin = vec![];
@@ -897,6 +1773,7 @@ mod tests {
         let initial_state = State {
             pos: 0,
             cells: [0; NUMBER_OF_CELLS],
+            ..State::default()
         };
         let mut s = initial_state.clone();
 
@@ -924,6 +1801,34 @@ mod tests {
         assert_eq!(s.cells[3..], initial_state.cells[3..]);
     }
 
+    #[test]
+    fn it_should_run_loops_at_an_offset() {
+        let stdin = vec![];
+        let mut stdout = vec![];
+        let mut initial_state = State {
+            pos: 0,
+            cells: [0; NUMBER_OF_CELLS],
+            ..State::default()
+        };
+        initial_state.cells[2] = 3;
+        initial_state.cells[3] = 1;
+        initial_state.cells[4] = 1;
+
+        let mut s = initial_state.clone();
+
+        // This code piece clears the cells starting two cells to the right of the current cell
+        let code = vec![Node::new_loop(
+            2,
+            1,
+            vec![Node::Assign(0, 2, false), Node::Shift(1)],
+        )];
+
+        run_block(&mut stdin.as_slice(), &mut stdout, &code, &mut s).unwrap();
+
+        assert_eq!(s.pos, 3);
+        assert_eq!(s.cells[0..], [0; NUMBER_OF_CELLS][0..]);
+    }
+
     #[test]
     fn it_should_not_run_nested_code_if_condition_is_false() {
         let stdin = vec![];
@@ -931,6 +1836,7 @@ mod tests {
         let initial_state = State {
             pos: 0,
             cells: [0; NUMBER_OF_CELLS],
+            ..State::default()
         };
         let mut s = initial_state.clone();
 
@@ -952,4 +1858,140 @@ mod tests {
         assert_eq!(s.pos, 0);
         assert_eq!(s.cells[0..], initial_state.cells[0..]);
     }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn it_should_serialize_states_sparsely() {
+        let mut s = State {
+            pos: 3,
+            ..State::default()
+        };
+        s.cells[2] = 7;
+        s.cells[NUMBER_OF_CELLS - 1] = 1;
+
+        let json = serde_json::to_string(&s).unwrap();
+        assert_eq!(
+            json,
+            "{\"pos\":3,\"cells\":[[2,7],[65535,1]],\"eof\":\"Error\"}"
+        );
+
+        let deserialized: State = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.pos, s.pos);
+        assert_eq!(deserialized.cells[..], s.cells[..]);
+
+        assert!(serde_json::from_str::<State>("{\"pos\":0,\"cells\":[[65536,1]]}").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn it_should_serialize_the_length_of_other_tapes() {
+        let mut s = State::<HeapTape>::with_len(10).unwrap();
+        *s.cells.get_mut(9) = 4;
+
+        let json = serde_json::to_string(&s).unwrap();
+        assert_eq!(
+            json,
+            "{\"pos\":0,\"len\":10,\"cells\":[[9,4]],\"eof\":\"Error\"}"
+        );
+
+        let deserialized: State<SparseTape> = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized.cells.nonzero(), vec![(9, 4)]);
+        assert!(serde_json::from_str::<State<HeapTape>>(
+            "{\"pos\":0,\"len\":10,\"cells\":[[10,1]]}"
+        )
+        .is_err());
+        assert!(serde_json::from_str::<State>("{\"pos\":0,\"len\":10,\"cells\":[]}").is_err());
+
+        let wide = "{\"pos\":0,\"len\":10,\"cells\":[[2,300]]}";
+        let deserialized: State<HeapTape<u16>> = serde_json::from_str(wide).unwrap();
+        assert_eq!(deserialized.cells.nonzero(), vec![(2, 300)]);
+        assert!(serde_json::from_str::<State<HeapTape>>(wide).is_err());
+    }
+
+    #[test]
+    fn it_should_not_make_nodes_larger_for_loops_with_an_offset() {
+        assert!(::std::mem::size_of::<Node>() <= 4 * ::std::mem::size_of::<usize>());
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn it_should_serialize_nodes() {
+        let code = vec![
+            Node::Mul(-1, 2, 1, true),
+            Node::new_loop(1, 2, vec![Node::Comment('x')]),
+        ];
+
+        let json = serde_json::to_string(&code).unwrap();
+        let deserialized: Vec<Node> = serde_json::from_str(&json).unwrap();
+        assert_eq!(deserialized, code);
+    }
+
+    #[test]
+    fn it_should_apply_the_eof_policy_when_there_is_no_input() {
+        let policies = [
+            (EofPolicy::Zero, Ok(0)),
+            (EofPolicy::MinusOne, Ok(255)),
+            (EofPolicy::Unchanged, Ok(7)),
+            (
+                EofPolicy::Error,
+                Err(RuntimeError::ReadError("No data from stdin".to_string())),
+            ),
+        ];
+
+        for (policy, expected) in policies.iter() {
+            let mut stdout = vec![];
+            let mut s = State {
+                eof: *policy,
+                ..State::default()
+            };
+            s.cells[1] = 7;
+
+            let result = Node::In(1, false)
+                .execute(&mut [].as_ref(), &mut stdout, &mut s)
+                .map(|_| s.cells[1]);
+
+            assert_eq!(result, *expected);
+        }
+    }
+
+    #[test]
+    fn it_should_read_again_when_reading_is_interrupted() {
+        struct Interrupted(bool);
+
+        impl Read for Interrupted {
+            fn read(&mut self, buf: &mut [u8]) -> ::std::io::Result<usize> {
+                if self.0 {
+                    buf[0] = 9;
+                    return Ok(1);
+                }
+                self.0 = true;
+                Err(ErrorKind::Interrupted.into())
+            }
+        }
+
+        let mut s = State::default();
+        Node::In(0, false)
+            .execute(&mut Interrupted(false), &mut vec![], &mut s)
+            .unwrap();
+
+        assert_eq!(s.cells[0], 9);
+    }
+
+    #[test]
+    fn it_should_format_runtime_errors() {
+        assert_eq!(
+            format!(
+                "{}",
+                RuntimeError::InfiniteLoop(Location { line: 2, column: 4 })
+            ),
+            "2:4: this loop never terminates"
+        );
+        assert_eq!(
+            format!(
+                "{}",
+                RuntimeError::ReadError("No data from stdin".to_string())
+            ),
+            "could not read input: No data from stdin"
+        );
+    }
 }